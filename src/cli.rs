@@ -0,0 +1,70 @@
+use std::env;
+
+// Flags that take a value ('--flag value' or '--flag=value'), every other flag is a switch
+const VALUE_FLAGS: &[&str] = &["port", "dir", "set", "profile"];
+
+#[derive(Debug, Default)]
+pub struct CliArgs {
+    pub command: Option<String>,
+    pub args: Vec<String>,
+    flags: Vec<(String, Option<String>)>,
+}
+
+impl CliArgs {
+    pub fn parse() -> Self {
+        Self::from_args(env::args().skip(1))
+    }
+
+    pub fn from_args(raw: impl IntoIterator<Item = String>) -> Self {
+        let mut cli = CliArgs::default();
+        let mut raw = raw.into_iter();
+        while let Some(arg) = raw.next() {
+            if let Some(flag) = arg.strip_prefix("--") {
+                match flag.split_once('=') {
+                    Some((name, value)) => {
+                        cli.flags.push((name.to_string(), Some(value.to_string())))
+                    }
+                    None if VALUE_FLAGS.contains(&flag) => {
+                        cli.flags.push((flag.to_string(), raw.next()))
+                    }
+                    None => cli.flags.push((flag.to_string(), None)),
+                }
+            } else if cli.command.is_none() {
+                cli.command = Some(arg);
+            } else {
+                cli.args.push(arg);
+            }
+        }
+        cli
+    }
+
+    pub fn command(&self) -> Option<&str> {
+        self.command.as_deref()
+    }
+
+    pub fn arg(&self, index: usize) -> Option<&str> {
+        self.args.get(index).map(String::as_str)
+    }
+
+    pub fn has_flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|(flag, _)| flag == name)
+    }
+
+    // Value of the last occurrence of a flag
+    pub fn flag_value(&self, name: &str) -> Option<&str> {
+        self.flags
+            .iter()
+            .rev()
+            .find(|(flag, _)| flag == name)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    // Values of every occurrence of a flag, in order
+    pub fn flag_values(&self, name: &str) -> Vec<&str> {
+        self.flags
+            .iter()
+            .filter(|(flag, _)| flag == name)
+            .filter_map(|(_, value)| value.as_deref())
+            .collect()
+    }
+}
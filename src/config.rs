@@ -0,0 +1,347 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cli::CliArgs;
+use crate::error::JavelinError;
+use crate::redact::mask;
+use crate::secrets::{is_reference, is_secret_key};
+use crate::utilities::{default_config, Config, SETTING_DEFAULTS};
+
+pub const PROJECT_CONFIG: &str = "javelin.conf.json";
+const ENV_PREFIX: &str = "JAVELIN_";
+
+// $XDG_CONFIG_HOME/javelin, or ~/.config/javelin on every platform (including macOS and Windows,
+// so the paths in the docs hold everywhere)
+pub fn javelin_config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))
+        .map(|dir| dir.join("javelin"))
+}
+
+// User level defaults shared by every project
+pub fn global_config_path() -> Option<PathBuf> {
+    javelin_config_dir().map(|dir| dir.join("config.toml"))
+}
+
+// The config merged from, lowest first: every key left blank, the global config.toml, the
+// project's javelin.conf.json, the selected profile, JAVELIN_* environment variables and
+// '--set key=value' flags, with SETTING_DEFAULTS filling in what's still blank. Profiles are
+// objects under "profiles" in either file, keyed by name. Empty values don't override the layers
+// below them, so the blank entries of a generated project config leave global values in place.
+pub struct LayeredConfig {
+    value: Value,
+    // Dotted key path of every value to the layer it came from
+    origins: BTreeMap<String, String>,
+}
+
+impl LayeredConfig {
+    pub fn load(
+        project_path: &str,
+        profile: Option<&str>,
+        cli: &CliArgs,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut config = LayeredConfig {
+            value: default_config(),
+            origins: BTreeMap::new(),
+        };
+
+        if let Some(path) = global_config_path().filter(|path| path.exists()) {
+            let layer: Value = toml::from_str(&fs::read_to_string(&path)?).map_err(|e| {
+                JavelinError::config(
+                    format!("Invalid {}: {}", path.display(), e),
+                    "Fix the TOML syntax at the position given",
+                )
+            })?;
+            config.merge(layer, &format!("global {}", path.display()));
+        }
+
+        if Path::new(project_path).exists() {
+            let layer: Value =
+                serde_json::from_str(&fs::read_to_string(project_path)?).map_err(|e| {
+                    JavelinError::config(
+                        format!("Invalid {}: {}", project_path, e),
+                        "Fix the JSON syntax at the line and column given, e.g. a trailing comma",
+                    )
+                })?;
+            config.merge(layer, &format!("project {}", project_path));
+        }
+
+        if let Some(profile) = profile {
+            let layer = config
+                .value
+                .get("profiles")
+                .and_then(|profiles| profiles.get(profile))
+                .cloned()
+                .ok_or_else(|| {
+                    JavelinError::config(
+                        format!("No profile '{}' under \"profiles\" in the config", profile),
+                        "Check the --profile name, 'javelin config show' lists the profiles",
+                    )
+                })?;
+            config.merge(layer, &format!("profile {}", profile));
+        }
+
+        for key in leaf_paths(&config.value) {
+            let name = format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase());
+            if let Ok(raw) = env::var(&name) {
+                config.set(&key, &raw, &format!("env {}", name));
+            }
+        }
+
+        for assignment in cli.flag_values("set") {
+            let (key, raw) = assignment.split_once('=').ok_or_else(|| {
+                JavelinError::config(
+                    format!("Expected --set key=value, got '{}'", assignment),
+                    "Pass settings as --set key.path=value",
+                )
+            })?;
+            config.set(key.trim(), raw, "cli --set");
+        }
+
+        config.apply_defaults();
+        Ok(config)
+    }
+
+    pub fn config(&self) -> Result<Config, Box<dyn Error>> {
+        serde_json::from_value(self.value.clone()).map_err(|e| {
+            JavelinError::config(
+                format!("Invalid setting: {}", e),
+                "Check the setting's type, 'javelin config show --origin' shows where it came from",
+            )
+            .into()
+        })
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    fn merge(&mut self, layer: Value, origin: &str) {
+        let mut leaves = Vec::new();
+        collect_leaves(&layer, "", &mut leaves);
+        for (key, value) in leaves {
+            if is_set(&value) {
+                insert(&mut self.value, &key, value);
+                self.origins.insert(key, origin.to_string());
+            }
+        }
+    }
+
+    // Fills in SETTING_DEFAULTS for the settings no layer set
+    fn apply_defaults(&mut self) {
+        for (key, default) in SETTING_DEFAULTS {
+            let current = key
+                .split('.')
+                .try_fold(&self.value, |value, segment| value.get(segment));
+            if !current.is_some_and(is_set) {
+                insert(&mut self.value, key, Value::String(default.to_string()));
+            }
+        }
+    }
+
+    // Sets a value given as text, typed after the value it replaces
+    fn set(&mut self, key: &str, raw: &str, origin: &str) {
+        let current = key
+            .split('.')
+            .try_fold(&self.value, |value, segment| value.get(segment));
+        let value = parse_value(current, raw);
+        if is_set(&value) {
+            insert(&mut self.value, key, value);
+            self.origins.insert(key.to_string(), origin.to_string());
+        }
+    }
+
+    // One 'key = value' line per setting, plaintext secrets masked
+    pub fn show(&self, with_origin: bool) {
+        for key in leaf_paths(&self.value) {
+            let Some(value) = key
+                .split('.')
+                .try_fold(&self.value, |value, segment| value.get(segment))
+            else {
+                continue;
+            };
+            let is_secret = is_secret_key(&key);
+            let text = match value {
+                Value::String(text) if is_secret && !is_reference(text) => mask(text).to_string(),
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            if with_origin {
+                let origin = self.origins.get(&key).map_or("default", String::as_str);
+                println!("{} = {}  ({})", key, text, origin);
+            } else {
+                println!("{} = {}", key, text);
+            }
+        }
+    }
+}
+
+// Empty strings and lists count as unset
+fn is_set(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::String(text) => !text.trim().is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        _ => true,
+    }
+}
+
+fn collect_leaves(value: &Value, prefix: &str, leaves: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                collect_leaves(value, &path, leaves);
+            }
+        }
+        leaf => leaves.push((prefix.to_string(), leaf.clone())),
+    }
+}
+
+fn leaf_paths(value: &Value) -> Vec<String> {
+    let mut leaves = Vec::new();
+    collect_leaves(value, "", &mut leaves);
+    leaves.into_iter().map(|(key, _)| key).collect()
+}
+
+fn insert(root: &mut Value, key: &str, value: Value) {
+    let mut current = root;
+    for segment in key.split('.') {
+        if !current.is_object() {
+            *current = Value::Object(Map::new());
+        }
+        current = current
+            .as_object_mut()
+            .expect("replaced with an object above")
+            .entry(segment)
+            .or_insert(Value::Null);
+    }
+    *current = value;
+}
+
+// Text from the environment or command line as the type of the value it replaces. Lists are comma
+// separated or a JSON array.
+fn parse_value(current: Option<&Value>, raw: &str) -> Value {
+    let raw = raw.trim();
+    match current {
+        Some(Value::String(_)) => Value::String(raw.to_string()),
+        Some(Value::Array(_)) if !raw.starts_with('[') => Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| match item.parse::<u64>() {
+                    Ok(number) => Value::from(number),
+                    Err(_) => Value::String(item.to_string()),
+                })
+                .collect(),
+        ),
+        _ => serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn defaults() -> LayeredConfig {
+        LayeredConfig {
+            value: default_config(),
+            origins: BTreeMap::new(),
+        }
+    }
+
+    fn get<'a>(config: &'a LayeredConfig, key: &str) -> &'a Value {
+        key.split('.')
+            .try_fold(&config.value, |value, segment| value.get(segment))
+            .unwrap_or(&Value::Null)
+    }
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let mut config = defaults();
+        config.merge(
+            json!({"github_username": "global-user", "s3": {"bucket": "global"}}),
+            "global",
+        );
+        config.merge(
+            json!({"github_username": "project-user", "s3": {"region": "eu-west-1"}}),
+            "project",
+        );
+
+        assert_eq!(get(&config, "github_username"), "project-user");
+        assert_eq!(get(&config, "s3.bucket"), "global");
+        assert_eq!(get(&config, "s3.region"), "eu-west-1");
+        assert_eq!(config.origins["github_username"], "project");
+        assert_eq!(config.origins["s3.bucket"], "global");
+    }
+
+    #[test]
+    fn empty_values_keep_lower_layers() {
+        let mut config = defaults();
+        config.merge(
+            json!({"github_pat": "env:TOKEN", "required_platforms": ["linux-x86_64"]}),
+            "global",
+        );
+        config.merge(
+            json!({"github_pat": "", "required_platforms": []}),
+            "project",
+        );
+
+        assert_eq!(get(&config, "github_pat"), "env:TOKEN");
+        assert_eq!(get(&config, "required_platforms"), &json!(["linux-x86_64"]));
+        assert_eq!(config.origins["github_pat"], "global");
+    }
+
+    #[test]
+    fn a_generated_config_keeps_global_hosts_and_defaults_fill_the_rest() {
+        let mut config = defaults();
+        config.merge(json!({"release_host": "s3"}), "global");
+        config.merge(default_config(), "project");
+        config.apply_defaults();
+
+        assert_eq!(get(&config, "release_host"), "s3");
+        assert_eq!(get(&config, "manifest_host"), "gist");
+        assert_eq!(get(&config, "local.channel"), "stable");
+        assert_eq!(get(&config, "manifest_branch.path"), "latest.json");
+        assert_eq!(config.origins["release_host"], "global");
+        assert!(!config.origins.contains_key("manifest_host"));
+    }
+
+    #[test]
+    fn set_parses_text_as_the_replaced_type() {
+        let mut config = defaults();
+        config.merge(
+            json!({"required_platforms": ["a"], "s3": {"path_style": false}}),
+            "project",
+        );
+
+        config.set(
+            "required_platforms",
+            "linux-x86_64, windows-x86_64",
+            "cli --set",
+        );
+        config.set("s3.path_style", "true", "cli --set");
+        config.set("github_username", "123", "cli --set");
+        config.set("brand_new.key", "[1, 2]", "cli --set");
+
+        assert_eq!(
+            get(&config, "required_platforms"),
+            &json!(["linux-x86_64", "windows-x86_64"])
+        );
+        assert_eq!(get(&config, "s3.path_style"), &json!(true));
+        assert_eq!(get(&config, "github_username"), &json!("123"));
+        assert_eq!(get(&config, "brand_new.key"), &json!([1, 2]));
+        assert_eq!(config.origins["s3.path_style"], "cli --set");
+    }
+}
@@ -0,0 +1,380 @@
+use reqwest::header::USER_AGENT;
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use crate::cli::CliArgs;
+use crate::config::{LayeredConfig, PROJECT_CONFIG};
+use crate::github::authenticated_user;
+use crate::keys::{format_key_id, key_id_hex, minisign_key_id, read_ledger};
+use crate::secrets::{plaintext_secrets, resolve_secrets};
+use crate::utilities::Config;
+
+// Prints each check as it runs and counts the problems
+#[derive(Default)]
+struct Report {
+    failures: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&mut self, message: &str) {
+        println!("[ok]   {}", message);
+    }
+
+    fn warn(&mut self, message: &str) {
+        self.warnings += 1;
+        println!("[warn] {}", message);
+    }
+
+    fn fail(&mut self, message: &str) {
+        self.failures += 1;
+        println!("[fail] {}", message);
+    }
+}
+
+// Runs the Tauri CLI the same way the build does
+fn tauri(base_dir: &str, args: &[&str]) -> Command {
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "npm run tauri", "--"]);
+        command
+    } else {
+        Command::new("tauri")
+    };
+    command.args(args).current_dir(base_dir);
+    command
+}
+
+pub fn tauri_command(base_dir: &str, args: &[&str]) -> std::io::Result<Output> {
+    tauri(base_dir, args).output()
+}
+
+// Runs the Tauri CLI with the signing key password in the environment (under the Tauri 2 and
+// Tauri 1 names), rather than as a '-p' argument other users can see in the process list
+pub fn tauri_command_with_password(
+    base_dir: &str,
+    args: &[&str],
+    password: &str,
+) -> std::io::Result<Output> {
+    tauri(base_dir, args)
+        .env("TAURI_SIGNING_PRIVATE_KEY_PASSWORD", password)
+        .env("TAURI_PRIVATE_KEY_PASSWORD", password)
+        .output()
+}
+
+// Signs a throwaway file with the secret key, which fails if the password doesn't decrypt it, and
+// returns the key id of the signature
+pub fn signing_key_id(
+    base_dir: &str,
+    key_path: &str,
+    password: &str,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let probe_dir = std::env::temp_dir().join(format!("javelin-doctor-{}", std::process::id()));
+    fs::create_dir_all(&probe_dir)?;
+    let probe = probe_dir.join("probe.txt");
+    fs::write(&probe, "javelin doctor")?;
+
+    let probe_arg = probe.to_string_lossy().into_owned();
+    let output = tauri_command_with_password(
+        base_dir,
+        &["signer", "sign", "-f", key_path, &probe_arg],
+        password,
+    );
+    let signature = fs::read_to_string(probe_dir.join("probe.txt.sig"));
+    let _ = fs::remove_dir_all(&probe_dir);
+
+    let output = output?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .to_string()
+            .into());
+    }
+    minisign_key_id(&signature?).ok_or_else(|| "Unreadable signature".into())
+}
+
+// Checks tauri.conf.json, returning the updater pubkey the build will use
+fn check_tauri_config(report: &mut Report, base_dir: &str, config: &Config) -> Option<String> {
+    let path = format!("{}/src-tauri/tauri.conf.json", base_dir);
+    let tauri_config: Value = match fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()))
+    {
+        Ok(tauri_config) => tauri_config,
+        Err(e) => {
+            report.fail(&format!("{} can't be read: {}", path, e));
+            return None;
+        }
+    };
+    report.ok(&format!("{} parses", path));
+
+    let updater = &tauri_config["tauri"]["updater"];
+    if updater["active"].as_bool() == Some(true) {
+        report.ok("Updater is active");
+    } else {
+        report.fail("Updater is not active, set tauri.updater.active to true");
+    }
+
+    // A profile's pubkey replaces the file's for the build
+    let pubkey = if config.build.pubkey.is_empty() {
+        updater["pubkey"].as_str().unwrap_or_default().to_string()
+    } else {
+        config.build.pubkey.clone()
+    };
+    if pubkey.trim().is_empty() {
+        report.fail("No updater pubkey, set tauri.updater.pubkey");
+        return None;
+    }
+    report.ok("Updater pubkey is set");
+    Some(pubkey)
+}
+
+fn check_signing_key(report: &mut Report, base_dir: &str, config: &Config, pubkey: Option<&str>) {
+    if config.secret_key_location.trim().is_empty() {
+        report.fail("secret_key_location is not set");
+        return;
+    }
+    let key_path = shellexpand::tilde(config.secret_key_location.trim()).into_owned();
+    if !Path::new(&key_path).exists() {
+        report.fail(&format!("Secret key file {} doesn't exist", key_path));
+        return;
+    }
+    report.ok(&format!("Secret key file {} exists", key_path));
+    // The signer runs in the project root
+    let key_path = fs::canonicalize(&key_path)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or(key_path);
+
+    let key_id = match signing_key_id(base_dir, &key_path, &config.secret_key_password) {
+        Ok(key_id) => key_id,
+        Err(e) => {
+            report.fail(&format!(
+                "Signing with the secret key failed, check secret_key_password: {}",
+                e
+            ));
+            return;
+        }
+    };
+    report.ok("Secret key decrypts with the password");
+
+    // During a rotation tauri.conf.json already holds the new pubkey while releases are signed
+    // with the old key, until the bridge release is out
+    if let Some(rotation) = read_ledger().ok().and_then(|ledger| ledger.pending) {
+        if format_key_id(&key_id).as_deref() == Some(rotation.from_key_id.as_str())
+            && pubkey.and_then(key_id_hex).as_deref() == Some(rotation.to_key_id.as_str())
+        {
+            report.ok(&format!(
+                "Rotating from key {} to {}, the next release is the bridge signed with the old key",
+                rotation.from_key_id, rotation.to_key_id
+            ));
+            return;
+        }
+    }
+
+    match pubkey.map(minisign_key_id) {
+        None => {}
+        Some(None) => report.fail("Updater pubkey isn't a valid Tauri public key"),
+        Some(Some(pubkey_id)) if pubkey_id == key_id => {
+            report.ok("Secret key matches the updater pubkey")
+        }
+        Some(Some(_)) => report
+            .fail("Secret key doesn't match the updater pubkey, clients would reject every update"),
+    }
+}
+
+async fn github_get(url: &str, token: &str) -> Result<reqwest::Response, reqwest::Error> {
+    reqwest::Client::new()
+        .get(url)
+        .header(USER_AGENT, "javelin")
+        .bearer_auth(token)
+        .send()
+        .await
+}
+
+async fn check_repo(report: &mut Report, repo: &str, token: &str) {
+    let response = match github_get(&format!("https://api.github.com/repos/{}", repo), token).await
+    {
+        Ok(response) => response,
+        Err(e) => {
+            report.fail(&format!("Couldn't reach Github for {}: {}", repo, e));
+            return;
+        }
+    };
+    if !response.status().is_success() {
+        report.fail(&format!(
+            "Repo {} not found or not visible to the PAT ({})",
+            repo,
+            response.status()
+        ));
+        return;
+    }
+    let details: Value = response.json().await.unwrap_or_default();
+    if details["permissions"]["push"].as_bool() == Some(true) {
+        report.ok(&format!("Repo {} exists and is writable", repo));
+    } else {
+        report.fail(&format!("The PAT can't push to {}", repo));
+    }
+}
+
+async fn check_github(report: &mut Report, config: &Config) {
+    if config.github_pat.trim().is_empty() {
+        report.fail("github_pat is not set");
+        return;
+    }
+    let token = config.github_pat.trim();
+
+    let user = match authenticated_user(token).await {
+        Ok(user) => user,
+        Err(e) => {
+            report.fail(&format!("Checking the PAT failed: {}", e));
+            return;
+        }
+    };
+    let login = user.login.clone();
+    report.ok(&format!("PAT is valid for {}", login));
+
+    match &user.scopes {
+        // Fine grained tokens have no scopes, their access shows in the repo checks below
+        None => report.warn("Fine grained PAT, its permissions are checked per repo only"),
+        Some(scopes) => {
+            let mut missing = Vec::new();
+            if !user.has_scope("repo") && !user.has_scope("public_repo") {
+                missing.push("repo");
+            }
+            if config.uses_gist() && !user.has_scope("gist") {
+                missing.push("gist");
+            }
+            if missing.is_empty() {
+                report.ok(&format!("PAT scopes: {}", scopes.join(", ")));
+            } else {
+                report.fail(&format!(
+                    "PAT is missing the {} scope",
+                    missing.join(" and ")
+                ));
+            }
+        }
+    }
+
+    check_repo(
+        report,
+        &format!("{}/{}", config.github_username, config.github_repo),
+        token,
+    )
+    .await;
+    if !config.distribution_repo.trim().is_empty() {
+        check_repo(report, config.distribution_repo.trim(), token).await;
+    }
+
+    if !config.uses_gist() {
+        return;
+    }
+    if config.gist_id.trim().is_empty() {
+        report.ok("No gist yet, one is created on the first release");
+        return;
+    }
+    let url = format!("https://api.github.com/gists/{}", config.gist_id.trim());
+    match github_get(&url, token).await {
+        Ok(response) if response.status().is_success() => {
+            let gist: Value = response.json().await.unwrap_or_default();
+            if gist["owner"]["login"].as_str() == Some(login.as_str()) {
+                report.ok(&format!("Gist {} exists and is writable", config.gist_id));
+            } else {
+                report.fail(&format!(
+                    "Gist {} belongs to another account, the PAT can't update it",
+                    config.gist_id
+                ));
+            }
+        }
+        Ok(response) => report.fail(&format!(
+            "Gist {} not found ({})",
+            config.gist_id,
+            response.status()
+        )),
+        Err(e) => report.fail(&format!("Couldn't reach Github: {}", e)),
+    }
+}
+
+// The project config may be committed as long as it holds no plaintext secrets
+fn check_git_ignored(report: &mut Report, plaintext: &[String]) {
+    let ignored = Command::new("git")
+        .args(["check-ignore", "-q", PROJECT_CONFIG])
+        .output()
+        .map(|output| output.status.code());
+    match ignored {
+        Ok(Some(0)) => report.ok(&format!("{} is git-ignored", PROJECT_CONFIG)),
+        Ok(Some(1)) if plaintext.is_empty() => report.ok(&format!(
+            "{} isn't git-ignored but holds no plaintext secrets",
+            PROJECT_CONFIG
+        )),
+        Ok(Some(1)) => report.fail(&format!(
+            "{} holds plaintext secrets and isn't git-ignored, add it to .gitignore or run 'javelin migrate-secrets'",
+            PROJECT_CONFIG
+        )),
+        _ => report.warn("Not a git repository, can't check .gitignore"),
+    }
+}
+
+// Checks everything a release needs up front, returns whether nothing failed
+pub async fn run_doctor(
+    base_dir: &str,
+    profile: Option<&str>,
+    cli: &CliArgs,
+) -> Result<bool, Box<dyn Error>> {
+    let mut report = Report::default();
+    println!("Checking release setup\n");
+
+    let layered = match LayeredConfig::load(PROJECT_CONFIG, profile, cli) {
+        Ok(layered) => layered,
+        Err(e) => {
+            report.fail(&format!("Config can't be loaded: {}", e));
+            return Ok(false);
+        }
+    };
+    let mut config = match layered.config() {
+        Ok(config) => config,
+        Err(e) => {
+            report.fail(&format!("Config is invalid: {}", e));
+            return Ok(false);
+        }
+    };
+    report.ok("Config loads");
+
+    let plaintext = plaintext_secrets(layered.value());
+    if !plaintext.is_empty() {
+        report.warn(&format!(
+            "{} stored in plaintext, run 'javelin migrate-secrets'",
+            plaintext.join(", ")
+        ));
+    }
+    match resolve_secrets(&mut config) {
+        Ok(()) => report.ok("Secret references resolve"),
+        Err(e) => report.fail(&format!("Secret reference doesn't resolve: {}", e)),
+    }
+
+    let pubkey = check_tauri_config(&mut report, base_dir, &config);
+
+    match tauri_command(base_dir, &["--version"]) {
+        Ok(output) if output.status.success() => report.ok(&format!(
+            "Tauri CLI installed: {}",
+            String::from_utf8_lossy(&output.stdout).trim()
+        )),
+        _ => report.fail("Tauri CLI not found, install it with 'cargo install tauri-cli'"),
+    }
+
+    check_signing_key(&mut report, base_dir, &config, pubkey.as_deref());
+
+    if config.uses_github() {
+        check_github(&mut report, &config).await;
+    }
+
+    check_git_ignored(&mut report, &plaintext);
+
+    println!(
+        "\n{} problem(s), {} warning(s)",
+        report.failures, report.warnings
+    );
+    Ok(report.failures == 0)
+}
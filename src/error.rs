@@ -0,0 +1,203 @@
+use async_trait::async_trait;
+use reqwest::{Response, StatusCode};
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+
+// Errors javelin knows how to explain. Each one carries a hint on how to fix it and exits with its
+// own code, so scripts and CI can tell a bad config from a failed build or an unreachable host.
+// Anything else still travels as a Box<dyn Error> and exits with 1.
+#[derive(Debug)]
+pub enum JavelinError {
+    // javelin.conf.json, tauri.conf.json, secret references or command line flags
+    Config {
+        message: String,
+        hint: &'static str,
+    },
+    // 'tauri build' or the bundle it should have produced
+    Build {
+        message: String,
+        hint: &'static str,
+    },
+    // The secret key, its password or the signature the build wrote
+    Signing {
+        message: String,
+        hint: &'static str,
+    },
+    // A release or manifest host answered with an error status
+    Http {
+        status: StatusCode,
+        url: String,
+        // The reason the host gave in its error body, if any
+        message: String,
+    },
+    // Reading, merging or publishing the updater manifest
+    Manifest {
+        message: String,
+        hint: &'static str,
+    },
+}
+
+impl JavelinError {
+    pub fn config(message: impl Into<String>, hint: &'static str) -> Self {
+        JavelinError::Config {
+            message: message.into(),
+            hint,
+        }
+    }
+
+    pub fn build(message: impl Into<String>, hint: &'static str) -> Self {
+        JavelinError::Build {
+            message: message.into(),
+            hint,
+        }
+    }
+
+    pub fn signing(message: impl Into<String>, hint: &'static str) -> Self {
+        JavelinError::Signing {
+            message: message.into(),
+            hint,
+        }
+    }
+
+    pub fn manifest(message: impl Into<String>, hint: &'static str) -> Self {
+        JavelinError::Manifest {
+            message: message.into(),
+            hint,
+        }
+    }
+
+    pub fn hint(&self) -> &'static str {
+        match self {
+            JavelinError::Config { hint, .. }
+            | JavelinError::Build { hint, .. }
+            | JavelinError::Signing { hint, .. }
+            | JavelinError::Manifest { hint, .. } => hint,
+            JavelinError::Http { status, .. } => match *status {
+                StatusCode::UNAUTHORIZED => {
+                    "The token is invalid or expired, create a new one and update the config"
+                }
+                StatusCode::FORBIDDEN => {
+                    "The token lacks a scope or permission for this, or the rate limit was hit. Check its scopes with 'javelin doctor'"
+                }
+                StatusCode::NOT_FOUND => {
+                    "Check the repo, gist or bucket exists and is visible to the token"
+                }
+                StatusCode::CONFLICT | StatusCode::UNPROCESSABLE_ENTITY => {
+                    "The release or asset may already exist, try increasing the version number"
+                }
+                status if status.is_server_error() => {
+                    "The host is having trouble, try again later with 'javelin resume'"
+                }
+                _ => "Check the host's message above, then retry with 'javelin resume'",
+            },
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            JavelinError::Config { .. } => 2,
+            JavelinError::Build { .. } => 3,
+            JavelinError::Signing { .. } => 4,
+            JavelinError::Http { .. } => 5,
+            JavelinError::Manifest { .. } => 6,
+        }
+    }
+}
+
+impl fmt::Display for JavelinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JavelinError::Config { message, .. }
+            | JavelinError::Build { message, .. }
+            | JavelinError::Signing { message, .. }
+            | JavelinError::Manifest { message, .. } => write!(f, "{}", message),
+            JavelinError::Http {
+                status,
+                url,
+                message,
+            } if message.is_empty() => write!(f, "{} returned {}", url, status),
+            JavelinError::Http {
+                status,
+                url,
+                message,
+            } => write!(f, "{} returned {}: {}", url, status, message),
+        }
+    }
+}
+
+impl Error for JavelinError {}
+
+// Prints an error and its hint, returning the code to exit with
+pub fn report(context: &str, error: &(dyn Error + 'static)) -> i32 {
+    eprintln!("{}: {}", context, error);
+    match error.downcast_ref::<JavelinError>() {
+        Some(error) => {
+            eprintln!("Hint: {}", error.hint());
+            error.exit_code()
+        }
+        None => 1,
+    }
+}
+
+// The reason in an error body: Github, GitLab and Gitea send JSON with a "message" (Github adds
+// per field "errors"), S3 sends XML with a <Message>
+fn error_message(body: &str) -> String {
+    if let Ok(value) = serde_json::from_str::<Value>(body) {
+        let mut message = value["message"]
+            .as_str()
+            .or_else(|| value["error"].as_str())
+            .unwrap_or_default()
+            .to_string();
+        let details: Vec<String> = value["errors"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|error| match error {
+                Value::String(text) => Some(text.clone()),
+                error => error["message"].as_str().map(str::to_string).or_else(|| {
+                    match (error["field"].as_str(), error["code"].as_str()) {
+                        (Some(field), Some(code)) => Some(format!("{} {}", field, code)),
+                        _ => None,
+                    }
+                }),
+            })
+            .collect();
+        if !details.is_empty() {
+            message = format!("{} ({})", message, details.join(", "));
+        }
+        return message;
+    }
+    if let (Some(start), Some(end)) = (body.find("<Message>"), body.find("</Message>")) {
+        return body[start + "<Message>".len()..end].to_string();
+    }
+    body.trim().chars().take(200).collect()
+}
+
+// The error for a response with an error status, reading the host's reason from the body
+pub async fn http_error(response: Response) -> JavelinError {
+    let status = response.status();
+    let url = response.url().to_string();
+    let body = response.text().await.unwrap_or_default();
+    JavelinError::Http {
+        status,
+        url,
+        message: error_message(&body),
+    }
+}
+
+// error_for_status() that keeps the host's reason for the failure
+#[async_trait(?Send)]
+pub trait CheckStatus: Sized {
+    async fn check_status(self) -> Result<Self, JavelinError>;
+}
+
+#[async_trait(?Send)]
+impl CheckStatus for Response {
+    async fn check_status(self) -> Result<Self, JavelinError> {
+        if self.status().is_client_error() || self.status().is_server_error() {
+            return Err(http_error(self).await);
+        }
+        Ok(self)
+    }
+}
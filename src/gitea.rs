@@ -0,0 +1,286 @@
+use async_trait::async_trait;
+use reqwest::multipart::{Form, Part};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{http_error, CheckStatus};
+use crate::release_host::{encode_segment, HostAsset, HostRelease, ReleaseHost};
+
+#[derive(Debug, Deserialize)]
+struct GiteaRelease {
+    id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaAttachment {
+    id: u64,
+    name: String,
+    browser_download_url: String,
+    created_at: Option<String>,
+}
+
+impl From<GiteaAttachment> for HostAsset {
+    fn from(attachment: GiteaAttachment) -> Self {
+        HostAsset {
+            id: attachment.id.to_string(),
+            name: attachment.name,
+            // The attachment API url returns metadata, clients need the download url
+            url: attachment.browser_download_url.clone(),
+            download_url: attachment.browser_download_url,
+            // Attachments are only listed once the upload has finished
+            complete: true,
+            label: None,
+            created_at: attachment.created_at,
+        }
+    }
+}
+
+// Gitea / Forgejo releases and release attachments, for any instance by base url
+pub struct GiteaReleaseHost {
+    repo_url: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+impl GiteaReleaseHost {
+    pub fn new(base_url: &str, repo: &str, token: &str) -> Self {
+        GiteaReleaseHost {
+            repo_url: format!(
+                "{}/api/v1/repos/{}",
+                base_url.trim_end_matches('/'),
+                repo.trim_matches('/')
+            ),
+            token: token.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}{}", self.repo_url, path))
+            .header("Authorization", format!("token {}", self.token))
+            .header("User-Agent", "javelin")
+    }
+
+    // Whether the tag exists, so a rollback only deletes tags that creating the release made
+    async fn tag_exists(&self, tag: &str) -> Result<bool, Box<dyn Error>> {
+        let response = self
+            .request(
+                reqwest::Method::GET,
+                &format!("/tags/{}", encode_segment(tag)),
+            )
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => Err(http_error(response).await.into()),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl ReleaseHost for GiteaReleaseHost {
+    fn name(&self) -> &'static str {
+        "gitea"
+    }
+
+    async fn find_or_create_release(
+        &self,
+        tag: &str,
+        notes: &str,
+    ) -> Result<HostRelease, Box<dyn Error>> {
+        let tag_path = format!("/releases/tags/{}", encode_segment(tag));
+        println!(
+            "\nChecking matching releases at: {}{}",
+            self.repo_url, tag_path
+        );
+
+        let response = self.request(reqwest::Method::GET, &tag_path).send().await?;
+
+        let (release, created, tag_created) = match response.status() {
+            StatusCode::OK => {
+                println!("Found existing release {}, using it for upload...", tag);
+                (response.json::<GiteaRelease>().await?, false, false)
+            }
+            StatusCode::NOT_FOUND => {
+                println!("No existing release found. Creating a new one...");
+                let tag_existed = self.tag_exists(tag).await?;
+                let release = self
+                    .request(reqwest::Method::POST, "/releases")
+                    .json(&json!({
+                        "tag_name": tag,
+                        "name": tag,
+                        "body": notes,
+                        "draft": false,
+                        "prerelease": false,
+                    }))
+                    .send()
+                    .await?
+                    .check_status()
+                    .await?
+                    .json::<GiteaRelease>()
+                    .await?;
+                (release, true, !tag_existed)
+            }
+            _ => return Err(http_error(response).await.into()),
+        };
+
+        Ok(HostRelease {
+            id: release.id.to_string(),
+            tag: tag.to_string(),
+            upload_url: String::new(),
+            created,
+            tag_created,
+        })
+    }
+
+    async fn upload_asset(
+        &self,
+        release: &HostRelease,
+        path: &Path,
+        _label: Option<&str>,
+    ) -> Result<HostAsset, Box<dyn Error>> {
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or("Asset path has no file name")?
+            .to_string();
+
+        let part = Part::bytes(fs::read(path)?)
+            .file_name(file_name.clone())
+            .mime_str("application/octet-stream")?;
+        let form = Form::new().part("attachment", part);
+
+        let response = self
+            .request(
+                reqwest::Method::POST,
+                &format!(
+                    "/releases/{}/assets?name={}",
+                    release.id,
+                    encode_segment(&file_name)
+                ),
+            )
+            .multipart(form)
+            .send()
+            .await?;
+
+        let attachment: GiteaAttachment = response.check_status().await?.json().await?;
+        println!("Asset uploaded: {}", attachment.browser_download_url);
+        Ok(attachment.into())
+    }
+
+    // Gitea accepts any number of attachments with the same name, so the attachments are listed
+    // before and after the upload. If two runs upload at once the lowest id wins and the other run
+    // deletes its own attachment again.
+    async fn create_asset(
+        &self,
+        release: &HostRelease,
+        path: &Path,
+        label: Option<&str>,
+    ) -> Result<Option<HostAsset>, Box<dyn Error>> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or("Asset path has no file name")?;
+        let lowest_id = |assets: Vec<HostAsset>| {
+            assets
+                .into_iter()
+                .filter(|asset| asset.name == name)
+                .filter_map(|asset| asset.id.parse::<u64>().ok())
+                .min()
+        };
+        if lowest_id(self.list_assets(release).await?).is_some() {
+            return Ok(None);
+        }
+
+        let asset = self.upload_asset(release, path, label).await?;
+        if lowest_id(self.list_assets(release).await?) != asset.id.parse().ok() {
+            self.delete_asset(release, &asset).await?;
+            return Ok(None);
+        }
+        Ok(Some(asset))
+    }
+
+    async fn list_assets(&self, release: &HostRelease) -> Result<Vec<HostAsset>, Box<dyn Error>> {
+        let attachments: Vec<GiteaAttachment> = self
+            .request(
+                reqwest::Method::GET,
+                &format!("/releases/{}/assets", release.id),
+            )
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .json()
+            .await?;
+        Ok(attachments.into_iter().map(HostAsset::from).collect())
+    }
+
+    async fn download_asset(
+        &self,
+        _release: &HostRelease,
+        asset: &HostAsset,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let content = self
+            .client
+            .get(&asset.download_url)
+            .header("Authorization", format!("token {}", self.token))
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .bytes()
+            .await?;
+        Ok(content.to_vec())
+    }
+
+    async fn delete_asset(
+        &self,
+        release: &HostRelease,
+        asset: &HostAsset,
+    ) -> Result<(), Box<dyn Error>> {
+        self.request(
+            reqwest::Method::DELETE,
+            &format!("/releases/{}/assets/{}", release.id, asset.id),
+        )
+        .send()
+        .await?
+        .check_status()
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_release(&self, release: &HostRelease) -> Result<(), Box<dyn Error>> {
+        self.request(
+            reqwest::Method::DELETE,
+            &format!("/releases/{}", release.id),
+        )
+        .send()
+        .await?
+        .check_status()
+        .await?;
+
+        if !release.tag_created {
+            return Ok(());
+        }
+        let tag_response = self
+            .request(
+                reqwest::Method::DELETE,
+                &format!("/tags/{}", encode_segment(&release.tag)),
+            )
+            .send()
+            .await?;
+
+        // The tag may already be gone
+        if !tag_response.status().is_success() && tag_response.status() != StatusCode::NOT_FOUND {
+            return Err(http_error(tag_response).await.into());
+        }
+
+        Ok(())
+    }
+}
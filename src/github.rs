@@ -1,3 +1,6 @@
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use reqwest::header::USER_AGENT;
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
@@ -8,33 +11,63 @@ use std::error::Error;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use std::process::Command;
 
+use crate::error::{http_error, CheckStatus, JavelinError};
+use crate::manifest::{version_key, GistContent, ManifestStore};
+use crate::release_host::{HostAsset, HostRelease, ReleaseHost};
 use crate::utilities::update_tauri_config_endpoint;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Asset {
+    id: u64,
+    name: String,
     url: String,                  // This is the API URL, which includes the asset ID.
     browser_download_url: String, // This is the direct download URL for the asset.
+    state: String,                // "uploaded" once the upload has completed
+    label: Option<String>,
+    created_at: Option<String>,
+}
+
+impl From<Asset> for HostAsset {
+    fn from(asset: Asset) -> Self {
+        HostAsset {
+            id: asset.id.to_string(),
+            name: asset.name,
+            url: asset.url,
+            download_url: asset.browser_download_url,
+            complete: asset.state == "uploaded",
+            label: asset.label.filter(|label| !label.is_empty()),
+            created_at: asset.created_at,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Release {
+    pub id: u64,
     name: String,
     pub upload_url: String,
+    // Set when this run created the release rather than reusing an existing one
+    #[serde(skip)]
+    pub created: bool,
+    // Set when the tag didn't exist before the release was created
+    #[serde(skip)]
+    pub tag_created: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct GistContent {
-    pub version: String,
-    pub notes: String,
-    pub pub_date: String,
-    pub platforms: HashMap<String, PlatformDetail>,
+// Single manifest shared by every platform
+pub fn manifest_filename(github_repo: &str) -> String {
+    format!("{}-javelin-manifest.json", github_repo)
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct PlatformDetail {
-    pub signature: String,
-    pub url: String,
+pub fn gist_raw_endpoint(github_username: &str, gist_id: &str, github_repo: &str) -> String {
+    format!(
+        "https://gist.github.com/{}/{}/raw/{}",
+        github_username,
+        gist_id,
+        manifest_filename(github_repo)
+    )
 }
 
 pub async fn get_matching_release(
@@ -87,11 +120,7 @@ pub async fn get_matching_release(
                 create_github_release(github_user_repo, new_version, release_notes, github_pat)
                     .await
             }
-            _ => Err(format!(
-                "Error fetching the latest release: HTTP Status {}",
-                resp.status()
-            )
-            .into()),
+            _ => Err(http_error(resp).await.into()),
         },
         Err(_e) => {
             // For simplicity, directly attempt to create a new release if there's an error
@@ -164,6 +193,24 @@ pub async fn get_matching_release(
 //     }
 // }
 
+// Whether the tag exists in the repo, whoever pushed it
+pub async fn github_tag_exists(repo: &str, tag: &str, token: &str) -> Result<bool, Box<dyn Error>> {
+    let response = reqwest::Client::new()
+        .get(format!(
+            "https://api.github.com/repos/{}/git/ref/tags/{}",
+            repo, tag
+        ))
+        .header(USER_AGENT, "javelin")
+        .bearer_auth(token)
+        .send()
+        .await?;
+    match response.status() {
+        StatusCode::OK => Ok(true),
+        StatusCode::NOT_FOUND => Ok(false),
+        _ => Err(http_error(response).await.into()),
+    }
+}
+
 pub async fn create_github_release(
     repo: &str,
     tag: &str,
@@ -172,6 +219,8 @@ pub async fn create_github_release(
 ) -> Result<Release, Box<dyn std::error::Error>> {
     let client = reqwest::Client::new();
     let url = format!("https://api.github.com/repos/{}/releases", repo);
+    // Checked first so a rollback only deletes the tag if creating the release created it
+    let tag_existed = github_tag_exists(repo, tag, token).await?;
 
     println!("Posting Release to url : \n{}", url);
 
@@ -188,24 +237,135 @@ pub async fn create_github_release(
         }))
         .send()
         .await?
-        .error_for_status()?
+        .check_status()
+        .await?
         .json::<Release>()
         .await?;
 
-    Ok(response)
+    Ok(Release {
+        created: true,
+        tag_created: !tag_existed,
+        ..response
+    })
+}
+
+// Deletes a release, and its tag if one is given
+pub async fn delete_github_release(
+    repo: &str,
+    release_id: u64,
+    tag: Option<&str>,
+    token: &str,
+) -> Result<(), Box<dyn Error>> {
+    let client = reqwest::Client::new();
+
+    client
+        .delete(format!(
+            "https://api.github.com/repos/{}/releases/{}",
+            repo, release_id
+        ))
+        .header(USER_AGENT, "javelin")
+        .bearer_auth(token)
+        .send()
+        .await?
+        .check_status()
+        .await?;
+
+    let Some(tag) = tag else {
+        return Ok(());
+    };
+    let tag_response = client
+        .delete(format!(
+            "https://api.github.com/repos/{}/git/refs/tags/{}",
+            repo, tag
+        ))
+        .header(USER_AGENT, "javelin")
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    // The tag may never have been pushed, that's fine
+    if !tag_response.status().is_success() && tag_response.status() != StatusCode::NOT_FOUND {
+        return Err(http_error(tag_response).await.into());
+    }
+
+    Ok(())
+}
+
+pub async fn delete_release_asset(asset_url: &str, token: &str) -> Result<(), Box<dyn Error>> {
+    let client = reqwest::Client::new();
+
+    client
+        .delete(asset_url)
+        .header(USER_AGENT, "javelin")
+        .bearer_auth(token)
+        .send()
+        .await?
+        .check_status()
+        .await?;
+
+    Ok(())
+}
+
+async fn list_release_assets(
+    repo: &str,
+    release_id: u64,
+    token: &str,
+) -> Result<Vec<Asset>, Box<dyn Error>> {
+    let client = reqwest::Client::new();
+    let assets = client
+        .get(format!(
+            "https://api.github.com/repos/{}/releases/{}/assets",
+            repo, release_id
+        ))
+        .header(USER_AGENT, "javelin")
+        .bearer_auth(token)
+        .send()
+        .await?
+        .check_status()
+        .await?
+        .json()
+        .await?;
+
+    Ok(assets)
+}
+
+pub async fn delete_gist(gist_id: &str, token: &str) -> Result<(), Box<dyn Error>> {
+    let client = reqwest::Client::new();
+
+    client
+        .delete(format!("https://api.github.com/gists/{}", gist_id))
+        .header(USER_AGENT, "javelin")
+        .bearer_auth(token)
+        .send()
+        .await?
+        .check_status()
+        .await?;
+
+    Ok(())
 }
 
 pub async fn upload_release_asset(
     upload_url: &str,
     filename: &Path,
+    label: Option<&str>,
     token: &str,
-) -> Result<String, Box<dyn std::error::Error>> {
+) -> Result<Asset, Box<dyn std::error::Error>> {
     let client = reqwest::Client::new();
     // Ensure the URL is correctly constructed to upload the asset
-    let url = upload_url.replace(
-        "{?name,label}",
-        &format!("?name={}", filename.file_name().unwrap().to_str().unwrap()),
-    );
+    let name = filename
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| {
+            JavelinError::build(
+                format!("Asset path {} has no file name", filename.display()),
+                "Check the bundle 'tauri build' produced, its file name must be valid UTF-8",
+            )
+        })?;
+    let mut query = format!("?name={}", name);
+    if let Some(label) = label {
+        query.push_str(&format!("&label={}", label.replace(' ', "%20")));
+    }
+    let url = upload_url.replace("{?name,label}", &query);
 
     let mut file = File::open(filename)?;
     let mut contents = Vec::new();
@@ -224,10 +384,150 @@ pub async fn upload_release_asset(
     if response.status().is_success() {
         let asset: Asset = response.json().await?;
         println!("Asset uploaded: {}", asset.url);
-        Ok(asset.url) // Return the URL that includes the asset ID
+        Ok(asset)
     } else {
-        // Handle error response...
-        Err(format!("Failed to upload asset. Status: {} : You may be trying to overwrite a current arch artifact. Try increase the version number?", response.status()).into())
+        // A 422 usually means this platform's artifact is already on the release
+        Err(http_error(response).await.into())
+    }
+}
+
+pub struct GithubReleaseHost {
+    github_user_repo: String,
+    token: String,
+    // Set when releases go to a public distribution repo rather than the source repo
+    source_repo: Option<String>,
+}
+
+impl GithubReleaseHost {
+    pub fn new(github_user_repo: &str, token: &str) -> Self {
+        GithubReleaseHost {
+            github_user_repo: github_user_repo.to_string(),
+            token: token.to_string(),
+            source_repo: None,
+        }
+    }
+
+    // Publishes to a separate, usually public, repo so the source repo can stay private. Release
+    // notes reference the source repo.
+    pub fn distribution(distribution_repo: &str, source_repo: &str, token: &str) -> Self {
+        GithubReleaseHost {
+            github_user_repo: distribution_repo.to_string(),
+            token: token.to_string(),
+            source_repo: Some(source_repo.to_string()),
+        }
+    }
+}
+
+// "owner/repo@<commit>" for the checked out commit, or "owner/repo <tag>" outside a git checkout
+fn source_reference(source_repo: &str, tag: &str) -> String {
+    let commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    match commit {
+        Some(commit) => format!("{}@{}", source_repo, commit),
+        None => format!("{} {}", source_repo, tag),
+    }
+}
+
+fn github_release_id(release: &HostRelease) -> Result<u64, Box<dyn Error>> {
+    release
+        .id
+        .parse()
+        .map_err(|_| format!("Invalid Github release id '{}'", release.id).into())
+}
+
+#[async_trait(?Send)]
+impl ReleaseHost for GithubReleaseHost {
+    fn name(&self) -> &'static str {
+        "github"
+    }
+
+    async fn find_or_create_release(
+        &self,
+        tag: &str,
+        notes: &str,
+    ) -> Result<HostRelease, Box<dyn Error>> {
+        let notes = match &self.source_repo {
+            Some(source_repo) => format!(
+                "{}\n\nBuilt from {}",
+                notes,
+                source_reference(source_repo, tag)
+            ),
+            None => notes.to_string(),
+        };
+        let release =
+            get_matching_release(&self.github_user_repo, tag, &notes, &self.token).await?;
+        Ok(HostRelease {
+            id: release.id.to_string(),
+            tag: tag.to_string(),
+            upload_url: release.upload_url,
+            created: release.created,
+            tag_created: release.tag_created,
+        })
+    }
+
+    async fn upload_asset(
+        &self,
+        release: &HostRelease,
+        path: &Path,
+        label: Option<&str>,
+    ) -> Result<HostAsset, Box<dyn Error>> {
+        let asset = upload_release_asset(&release.upload_url, path, label, &self.token).await?;
+        Ok(asset.into())
+    }
+
+    async fn list_assets(&self, release: &HostRelease) -> Result<Vec<HostAsset>, Box<dyn Error>> {
+        let assets = list_release_assets(
+            &self.github_user_repo,
+            github_release_id(release)?,
+            &self.token,
+        )
+        .await?;
+        Ok(assets.into_iter().map(HostAsset::from).collect())
+    }
+
+    async fn download_asset(
+        &self,
+        _release: &HostRelease,
+        asset: &HostAsset,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let content = reqwest::Client::new()
+            .get(&asset.url)
+            .header(USER_AGENT, "javelin")
+            .header("Accept", "application/octet-stream")
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .bytes()
+            .await?;
+        Ok(content.to_vec())
+    }
+
+    async fn delete_asset(
+        &self,
+        _release: &HostRelease,
+        asset: &HostAsset,
+    ) -> Result<(), Box<dyn Error>> {
+        let asset_url = format!(
+            "https://api.github.com/repos/{}/releases/assets/{}",
+            self.github_user_repo, asset.id
+        );
+        delete_release_asset(&asset_url, &self.token).await
+    }
+
+    async fn delete_release(&self, release: &HostRelease) -> Result<(), Box<dyn Error>> {
+        delete_github_release(
+            &self.github_user_repo,
+            github_release_id(release)?,
+            release.tag_created.then_some(release.tag.as_str()),
+            &self.token,
+        )
+        .await
     }
 }
 
@@ -236,15 +536,14 @@ pub async fn create_and_upload_gist(
     github_username: &str,
     token: &str,
     gist_content: &GistContent,
-    platform_key: &str,
     tauri_config_path: &str,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let client = reqwest::Client::new();
 
     let gist_file_content = serde_json::to_string_pretty(&gist_content)?;
 
-    let description = format!("{}-javelin-{}", github_repo, platform_key);
-    let filename = format!("{}-javelin-{}-manifest.json", github_repo, platform_key);
+    let description = format!("{}-javelin", github_repo);
+    let filename = manifest_filename(github_repo);
 
     println!("Uploading Gist Filename : {}", filename);
     println!("Description : {}", description);
@@ -268,7 +567,8 @@ pub async fn create_and_upload_gist(
         .json(&payload)
         .send()
         .await?
-        .error_for_status()?;
+        .check_status()
+        .await?;
 
     if response.status().is_success() {
         let gist_response: serde_json::Value = response.json().await?;
@@ -277,10 +577,7 @@ pub async fn create_and_upload_gist(
         println!("Gist ID : {:?}", gist_endpoint);
 
         if let Some(gist_id) = gist_response["id"].as_str() {
-            let gist_updater_endpoint = format!(
-                "https://gist.github.com/{}/{}/raw",
-                github_username, gist_id
-            );
+            let gist_updater_endpoint = gist_raw_endpoint(github_username, gist_id, github_repo);
             update_tauri_config_endpoint(tauri_config_path, &gist_updater_endpoint)?;
             Ok(gist_id.to_string())
         } else {
@@ -303,90 +600,796 @@ pub async fn create_and_upload_gist(
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-pub async fn fetch_and_update_gist(
-    github_repo: &str,
+// The account a PAT belongs to
+pub struct GithubUser {
+    pub login: String,
+    // Scopes of a classic PAT, None for fine grained tokens which don't have any
+    pub scopes: Option<Vec<String>>,
+}
+
+impl GithubUser {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes
+            .as_ref()
+            .is_some_and(|scopes| scopes.iter().any(|granted| granted == scope))
+    }
+}
+
+pub async fn authenticated_user(token: &str) -> Result<GithubUser, Box<dyn Error>> {
+    let response = reqwest::Client::new()
+        .get("https://api.github.com/user")
+        .header(USER_AGENT, "javelin")
+        .bearer_auth(token)
+        .send()
+        .await?;
+    let response = response.check_status().await?;
+
+    let scopes = response
+        .headers()
+        .get("X-OAuth-Scopes")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(',')
+                .map(|scope| scope.trim().to_string())
+                .filter(|scope| !scope.is_empty())
+                .collect()
+        });
+    let user: Value = response.json().await?;
+    Ok(GithubUser {
+        login: user["login"].as_str().unwrap_or_default().to_string(),
+        scopes,
+    })
+}
+
+// Files of a gist along with the revision they were read at
+struct GistSnapshot {
+    files: serde_json::Map<String, Value>,
+    revision: String,
+}
+
+async fn fetch_gist(
+    client: &reqwest::Client,
     token: &str,
     gist_id: &str,
-    new_version: &str,
-    new_notes: &str,
-    new_pub_date: &str,
-    platform_key: &str,
-    new_platform_detail: PlatformDetail,
-) -> Result<(), Box<dyn Error>> {
-    let client = reqwest::Client::new();
-    // Fetch the gist
+) -> Result<GistSnapshot, Box<dyn Error>> {
     let gist_url = format!("https://api.github.com/gists/{}", gist_id);
     let response = client
         .get(&gist_url)
         .header("User-Agent", "javelin")
+        .header("Cache-Control", "no-cache")
         .bearer_auth(token)
         .send()
         .await?;
 
-    if !response.status().is_success() {
-        return Err(format!("Failed to fetch gist: Status code {}", response.status()).into());
-    }
+    let response = response.check_status().await?;
 
     let mut gist: HashMap<String, Value> = response.json().await?;
 
-    let filename = format!("{}-javelin-{}-manifest.json", github_repo, platform_key);
+    // The newest history entry is the current revision, updated_at is a fallback
+    let revision = gist
+        .get("history")
+        .and_then(|history| history.get(0))
+        .and_then(|entry| entry["version"].as_str())
+        .or_else(|| gist.get("updated_at").and_then(|u| u.as_str()))
+        .unwrap_or_default()
+        .to_string();
 
-    if let Some(file) = gist
-        .get_mut("files")
-        .and_then(|f| f.as_object_mut())
-        .and_then(|files| files.get_mut(&filename))
-    {
-        if let Some(content) = file.get("content").and_then(|c| c.as_str()) {
-            let mut existing_content: GistContent = serde_json::from_str(content)?;
+    match gist.remove("files") {
+        Some(Value::Object(files)) => Ok(GistSnapshot { files, revision }),
+        _ => Err("Gist has no files".into()),
+    }
+}
 
-            // Update the version, notes, and pub_date fields
-            existing_content.version = new_version.to_string();
-            existing_content.notes = new_notes.to_string();
-            existing_content.pub_date = new_pub_date.to_string();
+fn file_content(file: &Value) -> Option<&str> {
+    file.get("content").and_then(|c| c.as_str())
+}
 
-            // Update or add the platform detail
-            existing_content
-                .platforms
-                .insert(platform_key.to_string(), new_platform_detail.clone());
+// The shared manifest as a file in a gist. A gist that still holds per platform manifests has to
+// be migrated first.
+pub struct GistManifestStore {
+    github_repo: String,
+    github_username: String,
+    token: String,
+    gist_id: String,
+    client: reqwest::Client,
+}
+
+impl GistManifestStore {
+    pub fn new(github_repo: &str, github_username: &str, token: &str, gist_id: &str) -> Self {
+        GistManifestStore {
+            github_repo: github_repo.to_string(),
+            github_username: github_username.to_string(),
+            token: token.to_string(),
+            gist_id: gist_id.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
 
-            // Serialize the updated content
-            let updated_content = serde_json::to_string_pretty(&existing_content)?;
+#[async_trait(?Send)]
+impl ManifestStore for GistManifestStore {
+    fn name(&self) -> &'static str {
+        "gist"
+    }
 
-            let update_payload = json!({
-                "files": {
-                    filename: {
-                        "content": updated_content
+    fn endpoint(&self) -> String {
+        gist_raw_endpoint(&self.github_username, &self.gist_id, &self.github_repo)
+    }
+
+    // The gist revision is the newest history entry
+    async fn read(&self) -> Result<Option<(GistContent, String)>, Box<dyn Error>> {
+        let snapshot = fetch_gist(&self.client, &self.token, &self.gist_id).await?;
+        let filename = manifest_filename(&self.github_repo);
+
+        let Some(file) = snapshot.files.get(&filename) else {
+            let prefix = format!("{}-javelin-", self.github_repo);
+            if snapshot
+                .files
+                .keys()
+                .any(|name| name.starts_with(&prefix) && name.ends_with("-manifest.json"))
+            {
+                return Err(
+                    "Gist still uses a per platform manifest, run 'javelin migrate-manifest' first"
+                        .into(),
+                );
+            }
+            return Ok(None);
+        };
+
+        let Some(content) = file_content(file) else {
+            return Err("file content not found".into());
+        };
+
+        Ok(Some((serde_json::from_str(content)?, snapshot.revision)))
+    }
+
+    // Gists have no conditional update, so the revision is only checked right before writing.
+    // A write from another runner can still land in between, publish_platform_release re-verifies.
+    async fn write(&self, manifest: &GistContent, revision: &str) -> Result<bool, Box<dyn Error>> {
+        let current = fetch_gist(&self.client, &self.token, &self.gist_id)
+            .await?
+            .revision;
+        if current != revision {
+            return Ok(false);
+        }
+
+        let update_payload = json!({
+            "files": {
+                manifest_filename(&self.github_repo): {
+                    "content": serde_json::to_string_pretty(manifest)?
+                }
+            }
+        });
+
+        let update_response = self
+            .client
+            .patch(format!("https://api.github.com/gists/{}", self.gist_id))
+            .header("User-Agent", "javelin")
+            .bearer_auth(&self.token)
+            .json(&update_payload)
+            .send()
+            .await?;
+
+        update_response.check_status().await?;
+
+        Ok(true)
+    }
+}
+
+// Lowest version wins so no platform is offered a release it hasn't published
+fn lowest_version<'a>(versions: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    versions.min_by_key(|version| version_key(version))
+}
+
+// Merges the per platform manifests of earlier javelin versions into the single shared manifest.
+// Legacy files are read from the target gist and any extra gists passed in, and removed from the
+// target gist once the shared manifest is written.
+pub async fn migrate_manifest(
+    github_repo: &str,
+    github_username: &str,
+    token: &str,
+    gist_id: &str,
+    legacy_gist_ids: &[String],
+    tauri_config_path: &str,
+) -> Result<GistContent, Box<dyn Error>> {
+    let client = reqwest::Client::new();
+    let filename = manifest_filename(github_repo);
+    let prefix = format!("{}-javelin-", github_repo);
+    let suffix = "-manifest.json";
+
+    let target_files = fetch_gist(&client, token, gist_id).await?.files;
+
+    let mut manifest = match target_files.get(&filename).and_then(file_content) {
+        Some(content) => serde_json::from_str::<GistContent>(content)?,
+        None => GistContent::default(),
+    };
+
+    let mut legacy_manifests: Vec<GistContent> = Vec::new();
+    let mut stale_files: Vec<String> = Vec::new();
+    let mut collect = |files: &serde_json::Map<String, Value>, is_target: bool| {
+        for (name, file) in files {
+            let is_legacy = name != &filename
+                && name.starts_with(&prefix)
+                && name.ends_with(suffix)
+                && name.len() > prefix.len() + suffix.len();
+            if !is_legacy {
+                continue;
+            }
+            let platform_key = &name[prefix.len()..name.len() - suffix.len()];
+            let Some(content) = file_content(file) else {
+                continue;
+            };
+            match serde_json::from_str::<GistContent>(content) {
+                Ok(mut legacy) => {
+                    println!(
+                        "Found {} manifest at version {}",
+                        platform_key, legacy.version
+                    );
+                    for detail in legacy.platforms.values_mut() {
+                        detail.version.get_or_insert_with(|| legacy.version.clone());
+                    }
+                    legacy_manifests.push(legacy);
+                    if is_target {
+                        stale_files.push(name.clone());
                     }
                 }
-            });
-
-            let update_response = client
-                .patch(&gist_url)
-                .header("User-Agent", "javelin")
-                .bearer_auth(token)
-                .json(&update_payload)
-                .send()
-                .await?;
-
-            if !update_response.status().is_success() {
-                return Err(format!(
-                    "Failed to update gist: Status code {}",
-                    update_response.status()
-                )
-                .into());
+                Err(e) => println!("Skipping {}: {}", name, e),
             }
+        }
+    };
+
+    collect(&target_files, true);
+    for legacy_gist_id in legacy_gist_ids {
+        let files = fetch_gist(&client, token, legacy_gist_id).await?.files;
+        collect(&files, false);
+    }
+
+    if legacy_manifests.is_empty() {
+        return Err("No per platform manifests found to migrate".into());
+    }
+
+    for legacy in &legacy_manifests {
+        for (platform_key, detail) in &legacy.platforms {
+            manifest
+                .platforms
+                .insert(platform_key.clone(), detail.clone());
+        }
+    }
+
+    let version = lowest_version(
+        manifest
+            .platforms
+            .values()
+            .filter_map(|detail| detail.version.as_deref()),
+    )
+    .unwrap_or_default()
+    .to_string();
+
+    if let Some(legacy) = legacy_manifests
+        .iter()
+        .find(|legacy| legacy.version == version)
+    {
+        manifest.notes = legacy.notes.clone();
+        manifest.pub_date = legacy.pub_date.clone();
+    }
+    manifest.version = version;
+
+    let mut files_payload = serde_json::Map::new();
+    files_payload.insert(
+        filename.clone(),
+        json!({ "content": serde_json::to_string_pretty(&manifest)? }),
+    );
+    // A null file entry deletes that file from the gist
+    for name in stale_files {
+        files_payload.insert(name, Value::Null);
+    }
+
+    let update_response = client
+        .patch(format!("https://api.github.com/gists/{}", gist_id))
+        .header("User-Agent", "javelin")
+        .bearer_auth(token)
+        .json(&json!({
+            "description": format!("{}-javelin", github_repo),
+            "files": files_payload
+        }))
+        .send()
+        .await?;
+
+    update_response.check_status().await?;
+
+    update_tauri_config_endpoint(
+        tauri_config_path,
+        &gist_raw_endpoint(github_username, gist_id, github_repo),
+    )?;
+
+    Ok(manifest)
+}
+
+// Writes a platform release into the per platform manifests left in legacy gists. Apps installed
+// before the migration still poll those gists, so they need the release that moves them to the
+// shared manifest endpoint. Gists without a file for the platform are left alone. Returns the ids
+// of the gists written to.
+pub async fn update_legacy_manifests(
+    github_repo: &str,
+    token: &str,
+    legacy_gist_ids: &[String],
+    platform_key: &str,
+    legacy: &GistContent,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let client = reqwest::Client::new();
+    let filename = format!("{}-javelin-{}-manifest.json", github_repo, platform_key);
+    let mut updated = Vec::new();
+
+    for gist_id in legacy_gist_ids {
+        let snapshot = fetch_gist(&client, token, gist_id).await?;
+        if !snapshot.files.contains_key(&filename) {
+            continue;
+        }
+        client
+            .patch(format!("https://api.github.com/gists/{}", gist_id))
+            .header("User-Agent", "javelin")
+            .bearer_auth(token)
+            .json(&json!({
+                "files": {
+                    &filename: { "content": serde_json::to_string_pretty(legacy)? }
+                }
+            }))
+            .send()
+            .await?
+            .check_status()
+            .await?;
+        updated.push(gist_id.clone());
+    }
+
+    Ok(updated)
+}
+
+#[derive(Debug, Deserialize)]
+struct RepoFile {
+    sha: String,
+    content: String,
+}
+
+// The manifest as a file committed to a repository branch through the Contents API, so the update
+// endpoint belongs to the repository rather than to whoever created a gist. Revisioned by the
+// file's blob sha.
+pub struct RepoManifestStore {
+    github_user_repo: String,
+    branch: String,
+    path: String,
+    public_url: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+impl RepoManifestStore {
+    pub fn new(
+        github_user_repo: &str,
+        branch: &str,
+        path: &str,
+        public_url: &str,
+        token: &str,
+    ) -> Self {
+        let branch = if branch.trim().is_empty() {
+            "updates"
         } else {
-            return Err("file content not found".into());
+            branch.trim()
+        };
+        let path = if path.trim().is_empty() {
+            "latest.json"
+        } else {
+            path.trim().trim_start_matches('/')
+        };
+        // raw.githubusercontent.com caches for a few minutes, a Pages url can be set instead
+        let public_url = if public_url.trim().is_empty() {
+            format!(
+                "https://raw.githubusercontent.com/{}/{}/{}",
+                github_user_repo, branch, path
+            )
+        } else {
+            public_url.trim().to_string()
+        };
+
+        RepoManifestStore {
+            github_user_repo: github_user_repo.to_string(),
+            branch: branch.to_string(),
+            path: path.to_string(),
+            public_url,
+            token: token.to_string(),
+            client: reqwest::Client::new(),
         }
-    } else {
-        return Err("File not found in the gist".into());
     }
-    // let gist_updater_endpoint = format!(
-    //     "https://gist.github.com/{}/{}/raw",
-    //     github_username, gist_id
-    // );
-    // update_tauri_config_endpoint(tauri_config_path, &gist_updater_endpoint)?;
 
-    Ok(())
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(
+                method,
+                format!(
+                    "https://api.github.com/repos/{}{}",
+                    self.github_user_repo, path
+                ),
+            )
+            .bearer_auth(&self.token)
+            .header(USER_AGENT, "javelin")
+            .header("Accept", "application/vnd.github+json")
+    }
+
+    async fn fetch_file(&self) -> Result<Option<RepoFile>, Box<dyn Error>> {
+        let response = self
+            .request(
+                reqwest::Method::GET,
+                &format!("/contents/{}?ref={}", self.path, self.branch),
+            )
+            .send()
+            .await?;
+
+        match response.status() {
+            // Either the file or the branch doesn't exist yet
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.json().await?)),
+            _ => Err(http_error(response).await.into()),
+        }
+    }
+
+    // Creates the branch from the head of the default branch if it doesn't exist yet
+    async fn ensure_branch(&self) -> Result<(), Box<dyn Error>> {
+        let response = self
+            .request(reqwest::Method::GET, &format!("/branches/{}", self.branch))
+            .send()
+            .await?;
+        if response.status().is_success() {
+            return Ok(());
+        }
+        if response.status() != StatusCode::NOT_FOUND {
+            return Err(http_error(response).await.into());
+        }
+
+        println!("Creating branch {} for the manifest", self.branch);
+        let repo: Value = self
+            .request(reqwest::Method::GET, "")
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .json()
+            .await?;
+        let default_branch = repo["default_branch"].as_str().unwrap_or("main");
+
+        let head: Value = self
+            .request(
+                reqwest::Method::GET,
+                &format!("/git/ref/heads/{}", default_branch),
+            )
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .json()
+            .await?;
+        let sha = head["object"]["sha"]
+            .as_str()
+            .ok_or("Default branch has no head commit")?;
+
+        self.request(reqwest::Method::POST, "/git/refs")
+            .json(&json!({
+                "ref": format!("refs/heads/{}", self.branch),
+                "sha": sha,
+            }))
+            .send()
+            .await?
+            .check_status()
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl ManifestStore for RepoManifestStore {
+    fn name(&self) -> &'static str {
+        "branch"
+    }
+
+    fn endpoint(&self) -> String {
+        self.public_url.clone()
+    }
+
+    async fn read(&self) -> Result<Option<(GistContent, String)>, Box<dyn Error>> {
+        let Some(file) = self.fetch_file().await? else {
+            return Ok(None);
+        };
+        // The API wraps the base64 content over several lines
+        let encoded: String = file.content.split_whitespace().collect();
+        let content = BASE64.decode(encoded)?;
+        Ok(Some((serde_json::from_slice(&content)?, file.sha)))
+    }
+
+    fn conditional_writes(&self) -> bool {
+        true
+    }
+
+    // The Contents API only replaces the file if the sha sent is still its current blob sha
+    async fn write(&self, manifest: &GistContent, revision: &str) -> Result<bool, Box<dyn Error>> {
+        if revision.is_empty() {
+            self.ensure_branch().await?;
+        }
+
+        let mut payload = json!({
+            "message": format!("Update {} to {}", self.path, manifest.version),
+            "content": BASE64.encode(serde_json::to_string_pretty(manifest)?),
+            "branch": self.branch,
+        });
+        if !revision.is_empty() {
+            payload["sha"] = json!(revision);
+        }
+
+        let response = self
+            .request(reqwest::Method::PUT, &format!("/contents/{}", self.path))
+            .json(&payload)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::CONFLICT => Ok(false),
+            // Sent without a sha, but another runner created the file first
+            StatusCode::UNPROCESSABLE_ENTITY
+                if revision.is_empty() && self.fetch_file().await?.is_some() =>
+            {
+                Ok(false)
+            }
+            _ => {
+                response.check_status().await?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+// Name the manifest is published under on each release, so the updater can always fetch it from
+// https://github.com/<owner>/<repo>/releases/latest/download/latest.json
+const RELEASE_MANIFEST_NAME: &str = "latest.json";
+
+// A release as the releases list returns it, with its assets inline
+#[derive(Debug, Deserialize)]
+struct ListedRelease {
+    tag_name: String,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+// The manifest asset to seed the release `tag` from: the one on the newest published release
+// other than `tag` itself. "releases/latest" can't be used, once the new release is created it is
+// the latest one and has no manifest yet. `releases` is in the API's order, newest first.
+fn seed_manifest_asset(releases: Vec<ListedRelease>, tag: &str) -> Option<Asset> {
+    releases
+        .into_iter()
+        .filter(|release| release.tag_name != tag && !release.draft && !release.prerelease)
+        .find_map(|release| {
+            release
+                .assets
+                .into_iter()
+                .find(|asset| asset.name == RELEASE_MANIFEST_NAME && asset.state == "uploaded")
+        })
+}
+
+// The manifest as a latest.json asset on the release being published, seeded from the previous
+// release's copy so platforms published in earlier releases stay listed. Revisioned by asset id.
+pub struct ReleaseAssetManifestStore {
+    github_user_repo: String,
+    tag: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+impl ReleaseAssetManifestStore {
+    pub fn new(github_user_repo: &str, tag: &str, token: &str) -> Self {
+        ReleaseAssetManifestStore {
+            github_user_repo: github_user_repo.to_string(),
+            tag: tag.to_string(),
+            token: token.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // The newest releases, which is as far back as a manifest to seed from is looked for
+    async fn list_releases(&self) -> Result<Vec<ListedRelease>, Box<dyn Error>> {
+        Ok(self
+            .client
+            .get(format!(
+                "https://api.github.com/repos/{}/releases?per_page=100",
+                self.github_user_repo
+            ))
+            .header(USER_AGENT, "javelin")
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .json()
+            .await?)
+    }
+
+    // The release at the given path, e.g. "tags/<tag>", None if there isn't one
+    async fn fetch_release(&self, path: &str) -> Result<Option<Release>, Box<dyn Error>> {
+        let response = self
+            .client
+            .get(format!(
+                "https://api.github.com/repos/{}/releases/{}",
+                self.github_user_repo, path
+            ))
+            .header(USER_AGENT, "javelin")
+            .bearer_auth(&self.token)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.json().await?)),
+            _ => Err(http_error(response).await.into()),
+        }
+    }
+
+    async fn find_manifest_asset(
+        &self,
+        release: &Release,
+        name: &str,
+    ) -> Result<Option<Asset>, Box<dyn Error>> {
+        let assets = list_release_assets(&self.github_user_repo, release.id, &self.token).await?;
+        Ok(assets
+            .into_iter()
+            .find(|asset| asset.name == name && asset.state == "uploaded"))
+    }
+
+    async fn download(&self, asset: &Asset) -> Result<GistContent, Box<dyn Error>> {
+        let content = self
+            .client
+            .get(&asset.url)
+            .header(USER_AGENT, "javelin")
+            .header("Accept", "application/octet-stream")
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .bytes()
+            .await?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+}
+
+#[async_trait(?Send)]
+impl ManifestStore for ReleaseAssetManifestStore {
+    fn name(&self) -> &'static str {
+        "release"
+    }
+
+    fn endpoint(&self) -> String {
+        format!(
+            "https://github.com/{}/releases/latest/download/{}",
+            self.github_user_repo, RELEASE_MANIFEST_NAME
+        )
+    }
+
+    async fn read(&self) -> Result<Option<(GistContent, String)>, Box<dyn Error>> {
+        let own = match self.fetch_release(&format!("tags/{}", self.tag)).await? {
+            Some(release) => {
+                self.find_manifest_asset(&release, RELEASE_MANIFEST_NAME)
+                    .await?
+            }
+            None => None,
+        };
+        let asset = match own {
+            Some(asset) => Some(asset),
+            None => seed_manifest_asset(self.list_releases().await?, &self.tag),
+        };
+        match asset {
+            Some(asset) => {
+                let manifest = self.download(&asset).await?;
+                Ok(Some((manifest, asset.id.to_string())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Assets can't be overwritten, so the new manifest is uploaded under a temporary name and
+    // renamed once the old one is removed, keeping the window without a manifest short. There's
+    // no conditional upload either, the revision is only checked before starting.
+    async fn write(&self, manifest: &GistContent, revision: &str) -> Result<bool, Box<dyn Error>> {
+        let current = self.read().await?.map(|(_, revision)| revision);
+        if current.unwrap_or_default() != revision {
+            return Ok(false);
+        }
+
+        let release = self
+            .fetch_release(&format!("tags/{}", self.tag))
+            .await?
+            .ok_or_else(|| format!("Release {} not found", self.tag))?;
+
+        let staging_name = format!("{}.new", RELEASE_MANIFEST_NAME);
+        let assets = list_release_assets(&self.github_user_repo, release.id, &self.token).await?;
+        // Left behind by a run that died part way through
+        for stale in assets.iter().filter(|asset| asset.name == staging_name) {
+            delete_release_asset(&stale.url, &self.token).await?;
+        }
+
+        let staging_path = std::env::temp_dir().join(&staging_name);
+        std::fs::write(&staging_path, serde_json::to_string_pretty(manifest)?)?;
+        let uploaded =
+            upload_release_asset(&release.upload_url, &staging_path, None, &self.token).await;
+        let _ = std::fs::remove_file(&staging_path);
+        let uploaded = uploaded?;
+
+        for old in assets
+            .iter()
+            .filter(|asset| asset.name == RELEASE_MANIFEST_NAME)
+        {
+            delete_release_asset(&old.url, &self.token).await?;
+        }
+
+        self.client
+            .patch(&uploaded.url)
+            .header(USER_AGENT, "javelin")
+            .bearer_auth(&self.token)
+            .json(&json!({ "name": RELEASE_MANIFEST_NAME }))
+            .send()
+            .await?
+            .check_status()
+            .await?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listed_release(tag: &str, assets: &[(u64, &str)]) -> serde_json::Value {
+        json!({
+            "tag_name": tag,
+            "draft": false,
+            "prerelease": false,
+            "assets": assets.iter().map(|(id, name)| json!({
+                "id": id,
+                "name": name,
+                "url": format!("https://api.github.com/assets/{}", id),
+                "browser_download_url": format!("https://github.com/download/{}", name),
+                "state": "uploaded",
+                "label": null,
+                "created_at": null,
+            })).collect::<Vec<_>>(),
+        })
+    }
+
+    #[test]
+    fn seeds_from_the_newest_other_release_with_a_manifest() {
+        // The release being published is already the latest, it has no manifest yet
+        let releases: Vec<ListedRelease> = serde_json::from_value(json!([
+            listed_release("v1.2.0", &[(5, "app.tar.gz")]),
+            listed_release("v1.1.0", &[(3, "app.tar.gz")]),
+            listed_release("v1.0.0", &[(1, "app.tar.gz"), (2, "latest.json")]),
+        ]))
+        .unwrap();
+
+        let seed = seed_manifest_asset(releases, "v1.2.0").unwrap();
+
+        assert_eq!(seed.id, 2);
+    }
+
+    #[test]
+    fn never_seeds_from_the_release_itself_or_a_prerelease() {
+        let mut prerelease = listed_release("v1.2.0-beta", &[(4, "latest.json")]);
+        prerelease["prerelease"] = json!(true);
+        let releases: Vec<ListedRelease> = serde_json::from_value(json!([
+            listed_release("v1.2.0", &[(6, "latest.json")]),
+            prerelease,
+        ]))
+        .unwrap();
+
+        assert!(seed_manifest_asset(releases, "v1.2.0").is_none());
+    }
 }
@@ -0,0 +1,585 @@
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{http_error, CheckStatus};
+use crate::release_host::{encode_segment, HostAsset, HostRelease, ReleaseHost};
+
+// Generic package the release assets are uploaded into, versioned by release tag
+const PACKAGE_NAME: &str = "javelin";
+
+#[derive(Debug, Deserialize)]
+struct GitlabLink {
+    id: u64,
+    name: String,
+    url: String,
+    direct_asset_url: Option<String>,
+}
+
+impl From<GitlabLink> for HostAsset {
+    fn from(link: GitlabLink) -> Self {
+        HostAsset {
+            id: link.id.to_string(),
+            name: link.name,
+            download_url: link.direct_asset_url.unwrap_or_else(|| link.url.clone()),
+            url: link.url,
+            // Links are only created once the package upload has finished
+            complete: true,
+            label: None,
+            created_at: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GitlabPackage {
+    id: u64,
+    version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitlabPackageFile {
+    id: u64,
+    file_name: String,
+}
+
+// GitLab Releases, with assets stored in the project's Generic Packages registry and linked from
+// the release. Works against gitlab.com or any self-hosted instance (or a mock server) by url.
+pub struct GitlabReleaseHost {
+    project_url: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+impl GitlabReleaseHost {
+    pub fn new(base_url: &str, project: &str, token: &str) -> Self {
+        let base_url = if base_url.trim().is_empty() {
+            "https://gitlab.com"
+        } else {
+            base_url.trim_end_matches('/')
+        };
+        GitlabReleaseHost {
+            project_url: format!("{}/api/v4/projects/{}", base_url, encode_segment(project)),
+            token: token.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}{}", self.project_url, path))
+            .header("PRIVATE-TOKEN", &self.token)
+            .header("User-Agent", "javelin")
+    }
+
+    fn package_file_url(&self, version: &str, file_name: &str) -> String {
+        format!(
+            "{}/packages/generic/{}/{}/{}",
+            self.project_url,
+            PACKAGE_NAME,
+            encode_segment(version),
+            encode_segment(file_name)
+        )
+    }
+
+    async fn default_branch(&self) -> Result<String, Box<dyn Error>> {
+        let project: serde_json::Value = self
+            .request(reqwest::Method::GET, "")
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .json()
+            .await?;
+        Ok(project["default_branch"]
+            .as_str()
+            .unwrap_or("main")
+            .to_string())
+    }
+
+    // Whether the tag exists, so a rollback only deletes tags that creating the release made
+    async fn tag_exists(&self, tag: &str) -> Result<bool, Box<dyn Error>> {
+        let response = self
+            .request(
+                reqwest::Method::GET,
+                &format!("/repository/tags/{}", encode_segment(tag)),
+            )
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => Err(http_error(response).await.into()),
+        }
+    }
+
+    // Removes the uploaded package file behind a release link, if it can still be found
+    async fn delete_package_file(
+        &self,
+        version: &str,
+        file_name: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let packages: Vec<GitlabPackage> = self
+            .request(
+                reqwest::Method::GET,
+                &format!(
+                    "/packages?package_type=generic&package_name={}",
+                    PACKAGE_NAME
+                ),
+            )
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .json()
+            .await?;
+
+        for package in packages.iter().filter(|package| package.version == version) {
+            let files: Vec<GitlabPackageFile> = self
+                .request(
+                    reqwest::Method::GET,
+                    &format!("/packages/{}/package_files", package.id),
+                )
+                .send()
+                .await?
+                .check_status()
+                .await?
+                .json()
+                .await?;
+            for file in files.iter().filter(|file| file.file_name == file_name) {
+                self.request(
+                    reqwest::Method::DELETE,
+                    &format!("/packages/{}/package_files/{}", package.id, file.id),
+                )
+                .send()
+                .await?
+                .check_status()
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl ReleaseHost for GitlabReleaseHost {
+    fn name(&self) -> &'static str {
+        "gitlab"
+    }
+
+    async fn find_or_create_release(
+        &self,
+        tag: &str,
+        notes: &str,
+    ) -> Result<HostRelease, Box<dyn Error>> {
+        let release_path = format!("/releases/{}", encode_segment(tag));
+        println!(
+            "\nChecking matching releases at: {}{}",
+            self.project_url, release_path
+        );
+
+        let response = self
+            .request(reqwest::Method::GET, &release_path)
+            .send()
+            .await?;
+
+        let (created, tag_created) = match response.status() {
+            StatusCode::OK => {
+                println!("Found existing release {}, using it for upload...", tag);
+                (false, false)
+            }
+            StatusCode::NOT_FOUND => {
+                println!("No existing release found. Creating a new one...");
+                let tag_existed = self.tag_exists(tag).await?;
+                let git_ref = self.default_branch().await?;
+                self.request(reqwest::Method::POST, "/releases")
+                    .json(&json!({
+                        "tag_name": tag,
+                        "name": tag,
+                        "description": notes,
+                        "ref": git_ref,
+                    }))
+                    .send()
+                    .await?
+                    .check_status()
+                    .await?;
+                (true, !tag_existed)
+            }
+            _ => return Err(http_error(response).await.into()),
+        };
+
+        Ok(HostRelease {
+            id: tag.to_string(),
+            tag: tag.to_string(),
+            upload_url: String::new(),
+            created,
+            tag_created,
+        })
+    }
+
+    async fn upload_asset(
+        &self,
+        release: &HostRelease,
+        path: &Path,
+        _label: Option<&str>,
+    ) -> Result<HostAsset, Box<dyn Error>> {
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or("Asset path has no file name")?;
+        let package_url = self.package_file_url(&release.tag, file_name);
+
+        let response = self
+            .client
+            .put(&package_url)
+            .header("PRIVATE-TOKEN", &self.token)
+            .header("Content-Type", "application/octet-stream")
+            .body(fs::read(path)?)
+            .send()
+            .await?;
+
+        response.check_status().await?;
+
+        let link: GitlabLink = self
+            .request(
+                reqwest::Method::POST,
+                &format!("/releases/{}/assets/links", encode_segment(&release.tag)),
+            )
+            .json(&json!({
+                "name": file_name,
+                "url": package_url,
+                "link_type": "package",
+            }))
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .json()
+            .await?;
+
+        println!("Asset uploaded: {}", link.url);
+        Ok(link.into())
+    }
+
+    async fn list_assets(&self, release: &HostRelease) -> Result<Vec<HostAsset>, Box<dyn Error>> {
+        let links: Vec<GitlabLink> = self
+            .request(
+                reqwest::Method::GET,
+                &format!("/releases/{}/assets/links", encode_segment(&release.tag)),
+            )
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .json()
+            .await?;
+        Ok(links.into_iter().map(HostAsset::from).collect())
+    }
+
+    async fn download_asset(
+        &self,
+        release: &HostRelease,
+        asset: &HostAsset,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let content = self
+            .client
+            .get(self.package_file_url(&release.tag, &asset.name))
+            .header("PRIVATE-TOKEN", &self.token)
+            .send()
+            .await?
+            .check_status()
+            .await?
+            .bytes()
+            .await?;
+        Ok(content.to_vec())
+    }
+
+    async fn delete_asset(
+        &self,
+        release: &HostRelease,
+        asset: &HostAsset,
+    ) -> Result<(), Box<dyn Error>> {
+        self.request(
+            reqwest::Method::DELETE,
+            &format!(
+                "/releases/{}/assets/links/{}",
+                encode_segment(&release.tag),
+                asset.id
+            ),
+        )
+        .send()
+        .await?
+        .check_status()
+        .await?;
+
+        self.delete_package_file(&release.tag, &asset.name).await
+    }
+
+    async fn delete_release(&self, release: &HostRelease) -> Result<(), Box<dyn Error>> {
+        self.request(
+            reqwest::Method::DELETE,
+            &format!("/releases/{}", encode_segment(&release.tag)),
+        )
+        .send()
+        .await?
+        .check_status()
+        .await?;
+
+        if !release.tag_created {
+            return Ok(());
+        }
+        let tag_response = self
+            .request(
+                reqwest::Method::DELETE,
+                &format!("/repository/tags/{}", encode_segment(&release.tag)),
+            )
+            .send()
+            .await?;
+
+        // The tag may already be gone
+        if !tag_response.status().is_success() && tag_response.status() != StatusCode::NOT_FOUND {
+            return Err(http_error(tag_response).await.into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const PROJECT: &str = "/api/v4/projects/group%2Fapp";
+
+    // A local HTTP server answering "METHOD path" requests from a fixed table, 404 for anything
+    // else. Returns its url and the requests it received, in order.
+    async fn stub_server(
+        routes: Vec<(String, u16, &'static str)>,
+    ) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let routes = routes.clone();
+                let received = received.clone();
+                tokio::spawn(async move {
+                    let mut buffer = Vec::new();
+                    let mut chunk = [0u8; 4096];
+                    loop {
+                        // A request is complete once its headers and Content-Length body are in
+                        let Some(header_end) =
+                            buffer.windows(4).position(|window| window == b"\r\n\r\n")
+                        else {
+                            match socket.read(&mut chunk).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+                            }
+                            continue;
+                        };
+                        let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+                        let body_length = head
+                            .lines()
+                            .filter_map(|line| line.split_once(':'))
+                            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                            .map_or(0, |(_, value)| value.trim().parse().unwrap());
+                        if buffer.len() < header_end + 4 + body_length {
+                            match socket.read(&mut chunk).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+                            }
+                            continue;
+                        }
+                        buffer.drain(..header_end + 4 + body_length);
+
+                        let request_line = head.lines().next().unwrap_or_default();
+                        let request = request_line
+                            .rsplit_once(' ')
+                            .map_or(request_line, |(request, _)| request)
+                            .to_string();
+                        let (status, body) = routes
+                            .iter()
+                            .find(|(route, _, _)| *route == request)
+                            .map_or((404, "{}"), |(_, status, body)| (*status, *body));
+                        received.lock().unwrap().push(request);
+
+                        let response = format!(
+                            "HTTP/1.1 {} Stub\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                            status,
+                            body.len(),
+                            body
+                        );
+                        if socket.write_all(response.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        (url, requests)
+    }
+
+    fn route(request: &str, status: u16, body: &'static str) -> (String, u16, &'static str) {
+        (request.replace("{project}", PROJECT), status, body)
+    }
+
+    fn release(tag_created: bool) -> HostRelease {
+        HostRelease {
+            id: "v1.0.0".to_string(),
+            tag: "v1.0.0".to_string(),
+            upload_url: String::new(),
+            created: true,
+            tag_created,
+        }
+    }
+
+    #[tokio::test]
+    async fn creates_the_release_and_its_tag_on_the_default_branch() {
+        let (url, requests) = stub_server(vec![
+            route("GET {project}", 200, r#"{"default_branch":"trunk"}"#),
+            route("POST {project}/releases", 201, "{}"),
+        ])
+        .await;
+        let host = GitlabReleaseHost::new(&url, "group/app", "token");
+
+        let release = host
+            .find_or_create_release("v1.0.0", "notes")
+            .await
+            .unwrap();
+
+        assert!(release.created);
+        assert!(release.tag_created);
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                "GET /api/v4/projects/group%2Fapp/releases/v1.0.0",
+                "GET /api/v4/projects/group%2Fapp/repository/tags/v1.0.0",
+                "GET /api/v4/projects/group%2Fapp",
+                "POST /api/v4/projects/group%2Fapp/releases",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reuses_an_existing_release() {
+        let (url, requests) =
+            stub_server(vec![route("GET {project}/releases/v1.0.0", 200, "{}")]).await;
+        let host = GitlabReleaseHost::new(&url, "group/app", "token");
+
+        let release = host
+            .find_or_create_release("v1.0.0", "notes")
+            .await
+            .unwrap();
+
+        assert!(!release.created);
+        assert!(!release.tag_created);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn uploads_the_package_file_and_links_it() {
+        let (url, requests) = stub_server(vec![
+            route(
+                "PUT {project}/packages/generic/javelin/v1.0.0/app.tar.gz",
+                201,
+                "{}",
+            ),
+            route(
+                "POST {project}/releases/v1.0.0/assets/links",
+                201,
+                r#"{"id":7,"name":"app.tar.gz","url":"https://example.com/app.tar.gz","direct_asset_url":null}"#,
+            ),
+        ])
+        .await;
+        let host = GitlabReleaseHost::new(&url, "group/app", "token");
+        let dir = std::env::temp_dir().join(format!("javelin-gitlab-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.tar.gz");
+        fs::write(&path, "bundle").unwrap();
+
+        let asset = host.upload_asset(&release(true), &path, None).await;
+        let _ = fs::remove_dir_all(&dir);
+        let asset = asset.unwrap();
+
+        assert_eq!(asset.id, "7");
+        assert_eq!(asset.name, "app.tar.gz");
+        assert_eq!(asset.download_url, "https://example.com/app.tar.gz");
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn deleting_an_asset_removes_its_link_and_package_file() {
+        let (url, requests) = stub_server(vec![
+            route("DELETE {project}/releases/v1.0.0/assets/links/7", 200, "{}"),
+            route(
+                "GET {project}/packages?package_type=generic&package_name=javelin",
+                200,
+                r#"[{"id":3,"version":"v0.9.0"},{"id":4,"version":"v1.0.0"}]"#,
+            ),
+            route(
+                "GET {project}/packages/4/package_files",
+                200,
+                r#"[{"id":10,"file_name":"other.tar.gz"},{"id":11,"file_name":"app.tar.gz"}]"#,
+            ),
+            route("DELETE {project}/packages/4/package_files/11", 204, ""),
+        ])
+        .await;
+        let host = GitlabReleaseHost::new(&url, "group/app", "token");
+        let asset = HostAsset {
+            id: "7".to_string(),
+            name: "app.tar.gz".to_string(),
+            url: String::new(),
+            download_url: String::new(),
+            complete: true,
+            label: None,
+            created_at: None,
+        };
+
+        host.delete_asset(&release(true), &asset).await.unwrap();
+
+        assert_eq!(
+            requests.lock().unwrap().last().unwrap(),
+            "DELETE /api/v4/projects/group%2Fapp/packages/4/package_files/11"
+        );
+        assert_eq!(requests.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_release_keeps_a_tag_it_did_not_create() {
+        let routes = vec![
+            route("DELETE {project}/releases/v1.0.0", 200, "{}"),
+            route("DELETE {project}/repository/tags/v1.0.0", 204, ""),
+        ];
+        let (url, requests) = stub_server(routes.clone()).await;
+        GitlabReleaseHost::new(&url, "group/app", "token")
+            .delete_release(&release(false))
+            .await
+            .unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            ["DELETE /api/v4/projects/group%2Fapp/releases/v1.0.0"]
+        );
+
+        let (url, requests) = stub_server(routes).await;
+        GitlabReleaseHost::new(&url, "group/app", "token")
+            .delete_release(&release(true))
+            .await
+            .unwrap();
+        assert_eq!(
+            requests.lock().unwrap().last().unwrap(),
+            "DELETE /api/v4/projects/group%2Fapp/repository/tags/v1.0.0"
+        );
+    }
+}
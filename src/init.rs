@@ -0,0 +1,287 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::cli::CliArgs;
+use crate::config::{LayeredConfig, PROJECT_CONFIG};
+use crate::doctor::run_doctor;
+use crate::error::JavelinError;
+use crate::github::{authenticated_user, create_and_upload_gist};
+use crate::keys::generate_key_pair;
+use crate::manifest::{create_manifest_store, GistContent, UpdatePolicy};
+use crate::redact::register_secret;
+use crate::secrets::{is_reference, resolve_secret, resolve_secrets, store_secret};
+use crate::utilities::{
+    create_default_config_if_not_exists, patch_json_file, update_entry_in_config,
+    update_tauri_config_endpoint, Config,
+};
+
+// Asks a question, returning the answer or the default when nothing is entered
+pub fn ask(question: &str, default: &str) -> Result<String, Box<dyn Error>> {
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).map_err(|e| {
+        JavelinError::config(
+            format!("Couldn't read an answer from the terminal: {}", e),
+            "Run the command in an interactive terminal",
+        )
+    })?;
+    Ok(match answer.trim() {
+        "" => default.to_string(),
+        answer => answer.to_string(),
+    })
+}
+
+// Asks for a value unless the config already has one, saving new answers to the project config
+fn ask_setting(
+    key_path: &[&str],
+    question: &str,
+    current: &mut String,
+) -> Result<(), Box<dyn Error>> {
+    if current.trim().is_empty() {
+        *current = ask(question, "")?;
+        update_entry_in_config(PROJECT_CONFIG, key_path, current)?;
+    }
+    Ok(())
+}
+
+// Asks for a secret, which is saved as the reference given or stored in a private file and saved
+// as a file: reference. Returns the resolved value.
+fn ask_secret(key_path: &[&str], question: &str, current: &str) -> Result<String, Box<dyn Error>> {
+    let mut reference = current.trim().to_string();
+    if reference.is_empty() {
+        let answer = ask(
+            &format!("{} (or an env:, file: or cmd: reference)", question),
+            "",
+        )?;
+        reference = if answer.is_empty() || is_reference(&answer) {
+            answer
+        } else {
+            register_secret(&answer);
+            store_secret(&key_path.join("."), &answer)?
+        };
+        update_entry_in_config(PROJECT_CONFIG, key_path, &reference)?;
+    }
+    let value = resolve_secret(&reference)?;
+    register_secret(&value);
+    Ok(value)
+}
+
+// Generates a key pair with the Tauri CLI or imports an existing one, returning the updater pubkey
+fn setup_signing_key(
+    base_dir: &str,
+    config: &mut Config,
+    product_name: &str,
+) -> Result<String, Box<dyn Error>> {
+    let existing = shellexpand::tilde(config.secret_key_location.trim()).into_owned();
+    let key_path = if !existing.is_empty() && Path::new(&existing).exists() {
+        println!("Using the signing key at {}", existing);
+        existing
+    } else {
+        println!("[1] Generate a new signing key pair\n[2] Import an existing key");
+        match ask("Signing key", "1")?.as_str() {
+            "1" => {
+                let default_path = format!("~/.tauri/{}.key", product_name.replace(' ', "-"));
+                let location = ask("Save the key to", &default_path)?;
+                let key_path = shellexpand::tilde(&location).into_owned();
+                config.secret_key_password = ask_secret(
+                    &["secret_key_password"],
+                    "Password for the new key, leave empty for none",
+                    &config.secret_key_password,
+                )?;
+                generate_key_pair(base_dir, &key_path, &config.secret_key_password)?;
+                println!("Key pair written to {} and {}.pub", key_path, key_path);
+                println!("Back the private key up, updates can't be signed without it");
+                update_entry_in_config(PROJECT_CONFIG, &["secret_key_location"], &location)?;
+                key_path
+            }
+            "2" => {
+                let location = ask("Path of the existing key file", "")?;
+                let key_path = shellexpand::tilde(&location).into_owned();
+                if !Path::new(&key_path).exists() {
+                    return Err(JavelinError::signing(
+                        format!("No key file at {}", key_path),
+                        "Enter the path of the private key file, e.g. ~/.tauri/myapp.key",
+                    )
+                    .into());
+                }
+                config.secret_key_password = ask_secret(
+                    &["secret_key_password"],
+                    "Password of the key, leave empty for none",
+                    &config.secret_key_password,
+                )?;
+                update_entry_in_config(PROJECT_CONFIG, &["secret_key_location"], &location)?;
+                key_path
+            }
+            other => {
+                return Err(JavelinError::config(
+                    format!("Unknown choice '{}'", other),
+                    "Enter 1 to generate a new key pair or 2 to import an existing key",
+                )
+                .into())
+            }
+        }
+    };
+
+    let pubkey_path = format!("{}.pub", key_path);
+    let pubkey = match fs::read_to_string(&pubkey_path) {
+        Ok(pubkey) => pubkey.trim().to_string(),
+        Err(_) => ask(
+            &format!("No {} found, paste the public key", pubkey_path),
+            "",
+        )?,
+    };
+    if pubkey.is_empty() {
+        return Err(JavelinError::signing(
+            "A public key is needed to verify updates",
+            "Paste the contents of the .pub file generated with the private key",
+        )
+        .into());
+    }
+    Ok(pubkey)
+}
+
+// Adds a path relative to the working directory to the .gitignore there, where javelin.conf.json
+// lives (base_dir is the parent directory in debug builds)
+fn add_to_gitignore(entry: &str) -> Result<(), Box<dyn Error>> {
+    let path = ".gitignore";
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    if contents.lines().any(|line| line.trim() == entry) {
+        println!("{} is already in .gitignore", entry);
+        return Ok(());
+    }
+    let separator = if contents.is_empty() || contents.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    fs::write(path, format!("{}{}{}\n", contents, separator, entry))?;
+    println!("Added {} to .gitignore", entry);
+    Ok(())
+}
+
+// Sets up a Tauri project for its first auto updating release: the javelin config, a signing key
+// pair, the updater section of tauri.conf.json and the manifest host
+pub async fn run_init(base_dir: &str) -> Result<(), Box<dyn Error>> {
+    let tauri_config_path = format!("{}/src-tauri/tauri.conf.json", base_dir);
+    if !Path::new(&tauri_config_path).exists() {
+        return Err(JavelinError::config(
+            format!("Tauri config file not found at {}", tauri_config_path),
+            "Run 'javelin init' from the root of the Tauri project",
+        )
+        .into());
+    }
+    // Read loosely, a fresh project has no updater section yet
+    let tauri_config: Value = serde_json::from_str(&fs::read_to_string(&tauri_config_path)?)?;
+    let product_name = tauri_config["package"]["productName"]
+        .as_str()
+        .unwrap_or("app")
+        .to_string();
+
+    println!("Setting up auto updates for {}\n", product_name);
+    create_default_config_if_not_exists(PROJECT_CONFIG)?;
+    add_to_gitignore(PROJECT_CONFIG)?;
+    let mut config = LayeredConfig::load(PROJECT_CONFIG, None, &CliArgs::default())?.config()?;
+
+    if config.uses_github() {
+        println!("\n-[Github]-");
+        ask_setting(
+            &["github_username"],
+            "Github username",
+            &mut config.github_username,
+        )?;
+        ask_setting(
+            &["github_repo"],
+            "Github repo name",
+            &mut config.github_repo,
+        )?;
+        config.github_pat = ask_secret(&["github_pat"], "Github PAT", &config.github_pat)?;
+
+        let user = authenticated_user(&config.github_pat).await?;
+        println!("PAT is valid for {}", user.login);
+        if user.scopes.is_some() && config.uses_gist() && !user.has_scope("gist") {
+            return Err(JavelinError::config(
+                "The PAT can't publish the manifest to a gist",
+                "Add the gist scope to the PAT, or set manifest_host to another host",
+            )
+            .into());
+        }
+    }
+
+    println!("\n-[Signing key]-");
+    let pubkey = setup_signing_key(base_dir, &mut config, &product_name)?;
+    patch_json_file(
+        &tauri_config_path,
+        &[
+            (&["tauri", "updater", "active"], true.into()),
+            (&["tauri", "updater", "pubkey"], pubkey.into()),
+        ],
+    )?;
+    println!(
+        "Updater enabled in {} with the public key",
+        tauri_config_path
+    );
+
+    // The manifest store may need the other credentials, e.g. S3 keys
+    resolve_secrets(&mut config)?;
+    println!("\n-[Manifest]-");
+    if config.uses_gist() && config.gist_id.trim().is_empty() {
+        // A draft manifest without platforms, filled in by the first release
+        let draft = GistContent {
+            version: tauri_config["package"]["version"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            notes: "draft".to_string(),
+            pub_date: String::new(),
+            platforms: HashMap::new(),
+            pending: HashMap::new(),
+            policy: UpdatePolicy::default(),
+        };
+        let gist_id = create_and_upload_gist(
+            &config.github_repo,
+            &config.github_username,
+            &config.github_pat,
+            &draft,
+            &tauri_config_path,
+        )
+        .await?;
+        update_entry_in_config(PROJECT_CONFIG, &["gist_id"], &gist_id)?;
+        println!("Created the manifest gist {}", gist_id);
+    } else {
+        let store = create_manifest_store(
+            &config.manifest_host,
+            &config.github_repo,
+            &config.github_username,
+            &config.github_pat,
+            &config.gist_id,
+            &config.s3,
+            &config.local,
+            &config.manifest_branch,
+            &config.distribution_repo,
+            "",
+        )?;
+        update_tauri_config_endpoint(&tauri_config_path, &store.endpoint())?;
+        println!(
+            "Manifest is published to {} on the first release",
+            store.endpoint()
+        );
+    }
+    println!("Updater endpoint set in {}", tauri_config_path);
+
+    println!("\n-[Checking the setup]-");
+    if run_doctor(base_dir, None, &CliArgs::default()).await? {
+        println!("\nReady, run 'javelin' to publish the first release");
+    } else {
+        println!("\nFix the problems above before the first release");
+    }
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::redact::redact;
+use crate::release_host::{HostAsset, HostRelease};
+use crate::rollout::Rollout;
+
+const JOURNAL_DIR: &str = ".javelin/journal";
+
+// Record of a single platform release. Every step writes its output here as soon as it finishes
+// so 'javelin resume' can pick up from the first step that didn't.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct ReleaseJournal {
+    pub platform_key: String,
+    // Release profile this was published with, None for the base config
+    #[serde(default)]
+    pub profile: Option<String>,
+    pub previous_version: String,
+    pub new_version: String,
+    pub notes: String,
+    pub version_bumped: bool,
+    pub built: bool,
+    pub bundle_path: Option<String>,
+    pub artifact_path: Option<String>,
+    pub signature: Option<String>,
+    // Key id of the key the signature was made with, see 'javelin keys list'
+    #[serde(default)]
+    pub signing_key_id: Option<String>,
+    pub release: Option<HostRelease>,
+    pub asset_url: Option<String>,
+    #[serde(alias = "gist_revision")]
+    pub manifest_revision: Option<String>,
+    // When the manifest was updated, the pub_date clients are shown
+    #[serde(default)]
+    pub published_at: Option<String>,
+    // Staged rollout of this version through 'javelin serve', None offers it to everyone
+    #[serde(default)]
+    pub rollout: Option<Rollout>,
+    pub completed: bool,
+    // Remote objects created by this release, undone in reverse order if a later step fails
+    #[serde(default)]
+    pub created: Vec<RemoteObject>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RemoteObject {
+    Gist {
+        id: String,
+        // Config key the gist id was saved to, profiles save theirs under profiles.<name>
+        #[serde(default)]
+        key_path: Vec<String>,
+        // tauri.conf.json and its updater endpoints from before the gist, put back on rollback
+        #[serde(default)]
+        tauri_config_path: String,
+        #[serde(default)]
+        endpoints: Option<Vec<String>>,
+    },
+    Release {
+        release: HostRelease,
+    },
+    // Recorded before the upload starts so a half uploaded asset is cleaned up too,
+    // asset is filled in once the upload completes
+    Asset {
+        release: HostRelease,
+        name: String,
+        asset: Option<HostAsset>,
+    },
+    // "Release in progress" marker asset, always removed when the run ends
+    Marker {
+        release: HostRelease,
+        asset: HostAsset,
+    },
+}
+
+impl ReleaseJournal {
+    pub fn new(platform_key: &str, previous_version: &str, new_version: &str, notes: &str) -> Self {
+        ReleaseJournal {
+            platform_key: platform_key.to_string(),
+            previous_version: previous_version.to_string(),
+            new_version: new_version.to_string(),
+            notes: notes.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        journal_path(
+            &self.new_version,
+            &self.platform_key,
+            self.profile.as_deref(),
+        )
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = self.path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, redact(&serde_json::to_string_pretty(self)?))?;
+        Ok(())
+    }
+
+    // Name of the first step that hasn't finished, used for progress output
+    pub fn next_step(&self) -> &'static str {
+        if !self.version_bumped {
+            "version bump"
+        } else if !self.built {
+            "build"
+        } else if self.signature.is_none() {
+            "signature"
+        } else if self.artifact_path.is_none() {
+            "artifact rename"
+        } else if self.release.is_none() {
+            "release"
+        } else if self.asset_url.is_none() {
+            "asset upload"
+        } else if self.manifest_revision.is_none() {
+            "manifest update"
+        } else {
+            "done"
+        }
+    }
+}
+
+pub fn journal_path(version: &str, platform_key: &str, profile: Option<&str>) -> PathBuf {
+    let name = match profile {
+        Some(profile) => format!("{}-{}-{}.json", version, platform_key, profile),
+        None => format!("{}-{}.json", version, platform_key),
+    };
+    Path::new(JOURNAL_DIR).join(name)
+}
+
+pub fn read_journal<P: AsRef<Path>>(path: P) -> Result<ReleaseJournal, Box<dyn Error>> {
+    let journal_str = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&journal_str)?)
+}
+
+// The most recently modified incomplete journal for this platform and profile, or the one for
+// `version`
+pub fn find_incomplete_journal(
+    platform_key: &str,
+    version: Option<&str>,
+    profile: Option<&str>,
+) -> Result<ReleaseJournal, Box<dyn Error>> {
+    if let Some(version) = version {
+        let journal = read_journal(journal_path(version, platform_key, profile))
+            .map_err(|e| format!("No journal for {} on {}: {}", version, platform_key, e))?;
+        if journal.completed {
+            return Err(format!("Release {} already completed", version).into());
+        }
+        return Ok(journal);
+    }
+
+    let mut latest: Option<(std::time::SystemTime, PathBuf)> = None;
+    if Path::new(JOURNAL_DIR).exists() {
+        for entry in fs::read_dir(JOURNAL_DIR)? {
+            let entry = entry?;
+            let modified = entry.metadata()?.modified()?;
+            let path = entry.path();
+            let Ok(journal) = read_journal(&path) else {
+                continue;
+            };
+            if journal.completed
+                || journal.platform_key != platform_key
+                || journal.profile.as_deref() != profile
+            {
+                continue;
+            }
+            if latest.as_ref().is_none_or(|(time, _)| modified > *time) {
+                latest = Some((modified, path));
+            }
+        }
+    }
+
+    match latest {
+        Some((_, path)) => read_journal(path),
+        None => Err(format!("No incomplete release found for {}", platform_key).into()),
+    }
+}
+
+// Every release recorded in this project for the profile, in no particular order
+pub fn list_journals(profile: Option<&str>) -> Result<Vec<ReleaseJournal>, Box<dyn Error>> {
+    let mut journals = Vec::new();
+    if Path::new(JOURNAL_DIR).exists() {
+        for entry in fs::read_dir(JOURNAL_DIR)? {
+            if let Ok(journal) = read_journal(entry?.path()) {
+                if journal.profile.as_deref() == profile {
+                    journals.push(journal);
+                }
+            }
+        }
+    }
+    Ok(journals)
+}
@@ -0,0 +1,416 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::cli::CliArgs;
+use crate::config::{LayeredConfig, PROJECT_CONFIG};
+use crate::doctor::{signing_key_id, tauri_command_with_password};
+use crate::error::JavelinError;
+use crate::init::ask;
+use crate::journal::{list_journals, ReleaseJournal};
+use crate::manifest::{version_key, ManifestStore};
+use crate::redact::register_secret;
+use crate::secrets::{is_reference, resolve_secret, store_secret};
+use crate::utilities::{patch_json_file, update_entry_in_config};
+
+const KEY_LEDGER: &str = ".javelin/keys.json";
+
+// Every updater key this project has used and the releases each one signed
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct KeyLedger {
+    pub keys: Vec<SigningKey>,
+    // Rotation waiting for its bridge release, at most one at a time
+    #[serde(default)]
+    pub pending: Option<Rotation>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SigningKey {
+    // Key id as shown in the key's minisign comment
+    pub key_id: String,
+    pub location: String,
+    pub pubkey: String,
+    pub added_at: String,
+    // Release signed with the previous key that shipped this key's pubkey
+    #[serde(default)]
+    pub bridge_version: Option<String>,
+    #[serde(default)]
+    pub retired_at: Option<String>,
+    #[serde(default)]
+    pub signed: Vec<SignedRelease>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SignedRelease {
+    pub version: String,
+    pub platform_key: String,
+}
+
+// The new key is generated and its pubkey is in tauri.conf.json, releases are still signed with
+// the old key until the bridge release completes
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Rotation {
+    pub from_key_id: String,
+    pub to_key_id: String,
+    // secret_key_location and secret_key_password switched to once the bridge is out
+    pub location: String,
+    pub password: String,
+    pub started_at: String,
+}
+
+// The 8 byte key id of a Tauri pubkey or signature, both base64 encoded minisign files whose
+// second line holds a 2 byte algorithm, the key id and the key or signature
+pub fn minisign_key_id(encoded: &str) -> Option<Vec<u8>> {
+    let text = BASE64.decode(encoded.trim()).ok()?;
+    let text = String::from_utf8(text).ok()?;
+    let bytes = BASE64.decode(text.lines().nth(1)?.trim()).ok()?;
+    bytes.get(2..10).map(<[u8]>::to_vec)
+}
+
+// The key id the way minisign prints it, a little endian number in hex
+pub fn format_key_id(key_id: &[u8]) -> Option<String> {
+    let bytes: [u8; 8] = key_id.try_into().ok()?;
+    Some(format!("{:016X}", u64::from_le_bytes(bytes)))
+}
+
+pub fn key_id_hex(encoded: &str) -> Option<String> {
+    format_key_id(&minisign_key_id(encoded)?)
+}
+
+pub fn read_ledger() -> Result<KeyLedger, Box<dyn Error>> {
+    if !Path::new(KEY_LEDGER).exists() {
+        return Ok(KeyLedger::default());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(KEY_LEDGER)?)?)
+}
+
+fn save_ledger(ledger: &KeyLedger) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = Path::new(KEY_LEDGER).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(KEY_LEDGER, serde_json::to_string_pretty(ledger)?)?;
+    Ok(())
+}
+
+impl KeyLedger {
+    fn key_mut(&mut self, key_id: &str) -> Option<&mut SigningKey> {
+        self.keys.iter_mut().find(|key| key.key_id == key_id)
+    }
+
+    // Adds a key seen for the first time, e.g. the one a project started with
+    fn add_key(&mut self, key_id: &str, location: &str, pubkey: &str) {
+        if self.key_mut(key_id).is_none() {
+            self.keys.push(SigningKey {
+                key_id: key_id.to_string(),
+                location: location.to_string(),
+                pubkey: pubkey.to_string(),
+                added_at: Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                bridge_version: None,
+                retired_at: None,
+                signed: Vec::new(),
+            });
+        }
+    }
+}
+
+// Generates a key pair with the Tauri CLI, returning the pubkey. The password is passed in the
+// environment rather than on the command line, so the key is checked to decrypt with it
+// afterwards in case the CLI generated it without one.
+pub fn generate_key_pair(
+    base_dir: &str,
+    key_path: &str,
+    password: &str,
+) -> Result<String, Box<dyn Error>> {
+    if let Some(parent) = Path::new(key_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let output = tauri_command_with_password(
+        base_dir,
+        &["signer", "generate", "-w", key_path, "--ci"],
+        password,
+    )?;
+    if !output.status.success() {
+        return Err(JavelinError::signing(
+            format!(
+                "Generating the key failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            "Check the Tauri CLI is installed, 'tauri signer generate' is what javelin runs",
+        )
+        .into());
+    }
+
+    if !password.is_empty() {
+        if let Err(e) = signing_key_id(base_dir, key_path, password) {
+            let _ = fs::remove_file(key_path);
+            let _ = fs::remove_file(format!("{}.pub", key_path));
+            return Err(JavelinError::signing(
+                format!(
+                    "The generated key doesn't decrypt with the password, this Tauri CLI may not read it from TAURI_SIGNING_PRIVATE_KEY_PASSWORD: {}",
+                    e
+                ),
+                "Generate the key with 'tauri signer generate -w <path>', which prompts for the password, and set secret_key_location to it",
+            )
+            .into());
+        }
+    }
+
+    let pubkey = fs::read_to_string(format!("{}.pub", key_path))?;
+    Ok(pubkey.trim().to_string())
+}
+
+// Records the key that signed a release, returning its key id
+pub fn record_signature(
+    version: &str,
+    platform_key: &str,
+    signature: &str,
+    key_location: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let Some(key_id) = key_id_hex(signature) else {
+        return Ok(None);
+    };
+    let mut ledger = read_ledger()?;
+    ledger.add_key(&key_id, key_location, "");
+    let release = SignedRelease {
+        version: version.to_string(),
+        platform_key: platform_key.to_string(),
+    };
+    if let Some(key) = ledger.key_mut(&key_id) {
+        if !key.signed.contains(&release) {
+            key.signed.push(release);
+        }
+    }
+    save_ledger(&ledger)?;
+    Ok(Some(key_id))
+}
+
+// Starts a rotation: generates the new key and writes its pubkey into tauri.conf.json, so the
+// next release, still signed with the current key, is the bridge that teaches installed clients
+// to trust the new key. Starting again while a rotation is pending keeps the key generated first.
+pub fn start_rotation(base_dir: &str, tauri_config_path: &str) -> Result<(), Box<dyn Error>> {
+    let mut ledger = read_ledger()?;
+    if let Some(rotation) = &ledger.pending {
+        println!(
+            "Rotation from key {} to {} already started, publishing its bridge release",
+            rotation.from_key_id, rotation.to_key_id
+        );
+        return Ok(());
+    }
+
+    let config = LayeredConfig::load(PROJECT_CONFIG, None, &CliArgs::default())?.config()?;
+    let old_location = shellexpand::tilde(config.secret_key_location.trim()).into_owned();
+    if old_location.is_empty() || !Path::new(&old_location).exists() {
+        return Err(JavelinError::signing(
+            "No current signing key to rotate from",
+            "Set secret_key_location to the current private key, or run 'javelin init'",
+        )
+        .into());
+    }
+
+    let tauri_config: Value = serde_json::from_str(&fs::read_to_string(tauri_config_path)?)?;
+    let old_pubkey = tauri_config["tauri"]["updater"]["pubkey"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let from_key_id = key_id_hex(&old_pubkey).ok_or_else(|| {
+        JavelinError::signing(
+            "The updater pubkey in tauri.conf.json isn't a valid Tauri public key",
+            "Set tauri.updater.pubkey to the contents of the current key's .pub file",
+        )
+    })?;
+
+    println!("Current key : {} ({})", from_key_id, old_location);
+    let current = config.secret_key_location.trim();
+    let default_path = match current.strip_suffix(".key") {
+        Some(stem) => format!("{}-{}.key", stem, Utc::now().format("%Y%m%d")),
+        None => format!("{}-{}", current, Utc::now().format("%Y%m%d")),
+    };
+    let location = ask("Save the new key to", &default_path)?;
+    let key_path = shellexpand::tilde(&location).into_owned();
+    if Path::new(&key_path).exists() {
+        return Err(JavelinError::signing(
+            format!("{} already exists", key_path),
+            "Choose another path, an existing key is never overwritten",
+        )
+        .into());
+    }
+    let answer = ask(
+        "Password for the new key, leave empty for none (or an env:, file: or cmd: reference)",
+        "",
+    )?;
+    let password = if is_reference(&answer) {
+        resolve_secret(&answer)?
+    } else {
+        answer.clone()
+    };
+    register_secret(&password);
+
+    let pubkey = generate_key_pair(base_dir, &key_path, &password)?;
+    let to_key_id = key_id_hex(&pubkey).ok_or("The generated public key can't be read")?;
+    println!("New key {} written to {}", to_key_id, key_path);
+    println!("Back the new private key up, updates can't be signed without it");
+
+    // Each key keeps its own password file, the old one is still needed for the bridge
+    let password_reference = if answer.is_empty() || is_reference(&answer) {
+        answer
+    } else {
+        store_secret(&format!("secret_key_password-{}", to_key_id), &answer)?
+    };
+
+    patch_json_file(
+        tauri_config_path,
+        &[(&["tauri", "updater", "pubkey"], pubkey.clone().into())],
+    )?;
+    println!("New pubkey written to {}", tauri_config_path);
+
+    ledger.add_key(&from_key_id, &config.secret_key_location, &old_pubkey);
+    ledger.add_key(&to_key_id, &location, &pubkey);
+    ledger.pending = Some(Rotation {
+        from_key_id,
+        to_key_id,
+        location,
+        password: password_reference,
+        started_at: Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+    });
+    save_ledger(&ledger)?;
+    println!("Publishing the bridge release, signed with the current key\n");
+    Ok(())
+}
+
+// Switches to the new key once a release signed with the old key and shipping the new pubkey has
+// completed, and the manifest lists every required platform (every platform in it when none are
+// configured) at that version. Clients on a platform that hasn't published the bridge yet still
+// only trust the old key, so switching earlier would leave them without updates.
+pub async fn finish_rotation(
+    config_path: &str,
+    journal: &ReleaseJournal,
+    manifest_store: &dyn ManifestStore,
+    required_platforms: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let mut ledger = read_ledger()?;
+    let Some(rotation) = ledger.pending.clone() else {
+        return Ok(());
+    };
+    // Profiles ship their own pubkey, only a base release can be the bridge
+    if !journal.completed
+        || journal.profile.is_some()
+        || journal.signing_key_id.as_deref() != Some(rotation.from_key_id.as_str())
+    {
+        return Ok(());
+    }
+
+    let (manifest, _) = manifest_store.read().await?.unwrap_or_default();
+    let mut platforms: Vec<&String> = if required_platforms.is_empty() {
+        manifest.platforms.keys().collect()
+    } else {
+        required_platforms.iter().collect()
+    };
+    platforms.sort();
+    let missing: Vec<&str> = platforms
+        .into_iter()
+        .filter(|key| {
+            manifest
+                .platforms
+                .get(*key)
+                .and_then(|detail| detail.version.as_deref())
+                != Some(journal.new_version.as_str())
+        })
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(JavelinError::signing(
+            format!(
+                "Still signing with key {}: {} {} not published bridge release {} yet, their clients only trust the current key",
+                rotation.from_key_id,
+                missing.join(", "),
+                if missing.len() == 1 { "has" } else { "have" },
+                journal.new_version
+            ),
+            "Publish the same version on those platforms with the current key, then run 'javelin keys finish'",
+        )
+        .into());
+    }
+
+    update_entry_in_config(config_path, &["secret_key_location"], &rotation.location)?;
+    update_entry_in_config(config_path, &["secret_key_password"], &rotation.password)?;
+
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    if let Some(key) = ledger.key_mut(&rotation.from_key_id) {
+        key.retired_at = Some(now);
+    }
+    if let Some(key) = ledger.key_mut(&rotation.to_key_id) {
+        key.bridge_version = Some(journal.new_version.clone());
+    }
+    ledger.pending = None;
+    save_ledger(&ledger)?;
+
+    println!(
+        "\nBridge release {} is out, signing with key {} from now on",
+        journal.new_version, rotation.to_key_id
+    );
+    println!(
+        "secret_key_location set to {} in {}. Other machines releasing this app need the new key too",
+        rotation.location, config_path
+    );
+    Ok(())
+}
+
+// The newest completed base release signed with the key being rotated out, None when no rotation
+// is pending
+pub fn pending_bridge_release() -> Result<Option<ReleaseJournal>, Box<dyn Error>> {
+    let Some(rotation) = read_ledger()?.pending else {
+        return Ok(None);
+    };
+    list_journals(None)?
+        .into_iter()
+        .filter(|journal| {
+            journal.completed
+                && journal.signing_key_id.as_deref() == Some(rotation.from_key_id.as_str())
+        })
+        .max_by_key(|journal| version_key(&journal.new_version))
+        .map(Some)
+        .ok_or_else(|| {
+            JavelinError::signing(
+                format!(
+                    "No completed release is signed with key {} yet",
+                    rotation.from_key_id
+                ),
+                "Run javelin to publish the bridge release, it's signed with the current key",
+            )
+            .into()
+        })
+}
+
+// 'javelin keys list' prints every key and the releases it signed
+pub fn list_keys() -> Result<(), Box<dyn Error>> {
+    let ledger = read_ledger()?;
+    if ledger.keys.is_empty() {
+        println!("No signing keys recorded yet, keys are recorded as releases are signed");
+        return Ok(());
+    }
+    for key in &ledger.keys {
+        let status = match (&key.retired_at, &ledger.pending) {
+            (Some(retired_at), _) => format!("retired {}", retired_at),
+            (None, Some(rotation)) if rotation.to_key_id == key.key_id => {
+                "waiting for its bridge release".to_string()
+            }
+            (None, Some(rotation)) if rotation.from_key_id == key.key_id => {
+                "active, being rotated out".to_string()
+            }
+            (None, _) => "active".to_string(),
+        };
+        println!("{} ({}) : {}", key.key_id, key.location, status);
+        if let Some(bridge_version) = &key.bridge_version {
+            println!("  introduced by bridge release {}", bridge_version);
+        }
+        for release in &key.signed {
+            println!("  signed {} {}", release.version, release.platform_key);
+        }
+    }
+    Ok(())
+}
@@ -1 +1,2 @@
-pub mod utilities;
\ No newline at end of file
+pub mod error;
+pub mod utilities;
@@ -0,0 +1,316 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::JavelinError;
+use crate::manifest::{GistContent, ManifestStore};
+use crate::release_host::{HostAsset, HostRelease, ReleaseHost};
+use crate::utilities::LocalConfig;
+
+const MANIFEST_FILENAME: &str = "latest.json";
+const DEFAULT_CHANNEL: &str = "stable";
+// Files are copied under this suffix and renamed into place once complete, so the web server
+// never serves a half written artifact
+const PARTIAL_SUFFIX: &str = ".partial";
+
+// Writes file contents to a temporary sibling and renames it over the destination
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    let partial = partial_path(path);
+    fs::write(&partial, contents)?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(PARTIAL_SUFFIX);
+    PathBuf::from(partial)
+}
+
+fn sig_path(path: &Path) -> PathBuf {
+    let mut sig = path.as_os_str().to_owned();
+    sig.push(".sig");
+    PathBuf::from(sig)
+}
+
+// A directory tree laid out as <root>/<channel>/<version>/<file>, with the manifest at
+// <root>/<channel>/latest.json, served by a static web server from base_url
+#[derive(Clone)]
+pub struct LocalDir {
+    root: PathBuf,
+    channel: String,
+    base_url: String,
+}
+
+impl LocalDir {
+    pub fn new(config: &LocalConfig) -> Result<Self, Box<dyn Error>> {
+        if config.root.trim().is_empty() {
+            return Err(JavelinError::config(
+                "local.root is not set",
+                "Set local.root to the directory your web server serves",
+            )
+            .into());
+        }
+        if config.base_url.trim().is_empty() {
+            return Err(JavelinError::config(
+                "local.base_url is not set",
+                "Set local.base_url to the url local.root is served from, e.g. https://updates.example.com",
+            )
+            .into());
+        }
+        let channel = config.channel.trim().trim_matches('/');
+
+        Ok(LocalDir {
+            root: PathBuf::from(shellexpand::tilde(config.root.trim()).into_owned()),
+            channel: if channel.is_empty() {
+                DEFAULT_CHANNEL.to_string()
+            } else {
+                channel.to_string()
+            },
+            base_url: config.base_url.trim().trim_end_matches('/').to_string(),
+        })
+    }
+
+    // Path relative to the root, always '/' separated so it doubles as the url path
+    fn relative(&self, path: &str) -> String {
+        format!("{}/{}", self.channel, path)
+    }
+
+    fn path(&self, relative: &str) -> PathBuf {
+        relative
+            .split('/')
+            .fold(self.root.clone(), |path, segment| path.join(segment))
+    }
+
+    fn url(&self, relative: &str) -> String {
+        format!("{}/{}", self.base_url, relative)
+    }
+}
+
+pub struct LocalReleaseHost {
+    dir: LocalDir,
+}
+
+impl LocalReleaseHost {
+    pub fn new(dir: LocalDir) -> Self {
+        LocalReleaseHost { dir }
+    }
+
+    fn asset(&self, release: &HostRelease, name: &str, complete: bool) -> HostAsset {
+        let relative = format!("{}/{}", release.id, name);
+        let path = self.dir.path(&relative);
+        let url = self.dir.url(&relative);
+        let created_at = fs::metadata(if complete {
+            path.clone()
+        } else {
+            partial_path(&path)
+        })
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .map(|modified| DateTime::<Utc>::from(modified).to_rfc3339());
+
+        HostAsset {
+            id: path.to_string_lossy().into_owned(),
+            name: name.to_string(),
+            download_url: url.clone(),
+            url,
+            complete,
+            label: None,
+            created_at,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl ReleaseHost for LocalReleaseHost {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    // A release is the <channel>/<version> directory
+    async fn find_or_create_release(
+        &self,
+        tag: &str,
+        _notes: &str,
+    ) -> Result<HostRelease, Box<dyn Error>> {
+        let id = self.dir.relative(tag);
+        let path = self.dir.path(&id);
+        let created = !path.exists();
+        if created {
+            println!("Creating release directory {}", path.display());
+            fs::create_dir_all(&path)?;
+        } else {
+            println!("Found existing release directory {}", path.display());
+        }
+
+        Ok(HostRelease {
+            id,
+            tag: tag.to_string(),
+            upload_url: String::new(),
+            created,
+            tag_created: false,
+        })
+    }
+
+    // Copies the artifact, and its .sig file if there is one next to it
+    async fn upload_asset(
+        &self,
+        release: &HostRelease,
+        path: &Path,
+        _label: Option<&str>,
+    ) -> Result<HostAsset, Box<dyn Error>> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or("Asset path has no file name")?;
+        let destination = self.dir.path(&format!("{}/{}", release.id, name));
+
+        let source_sig = sig_path(path);
+        if source_sig.exists() {
+            write_atomically(&sig_path(&destination), &fs::read(&source_sig)?)?;
+        }
+        write_atomically(&destination, &fs::read(path)?)?;
+
+        let asset = self.asset(release, name, true);
+        println!("Asset published: {}", asset.url);
+        Ok(asset)
+    }
+
+    // Created with create_new, so of two runs writing the same asset only one succeeds
+    async fn create_asset(
+        &self,
+        release: &HostRelease,
+        path: &Path,
+        _label: Option<&str>,
+    ) -> Result<Option<HostAsset>, Box<dyn Error>> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or("Asset path has no file name")?;
+        let destination = self.dir.path(&format!("{}/{}", release.id, name));
+
+        let mut file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&destination)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        file.write_all(&fs::read(path)?)?;
+
+        let asset = self.asset(release, name, true);
+        println!("Asset published: {}", asset.url);
+        Ok(Some(asset))
+    }
+
+    async fn list_assets(&self, release: &HostRelease) -> Result<Vec<HostAsset>, Box<dyn Error>> {
+        let entries = match fs::read_dir(self.dir.path(&release.id)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut assets = Vec::new();
+        for entry in entries {
+            let file_name = entry?.file_name().to_string_lossy().into_owned();
+            let (name, complete) = match file_name.strip_suffix(PARTIAL_SUFFIX) {
+                Some(name) => (name, false),
+                None => (file_name.as_str(), true),
+            };
+            // Signatures are listed with the artifact they belong to
+            if !name.ends_with(".sig") {
+                assets.push(self.asset(release, name, complete));
+            }
+        }
+        Ok(assets)
+    }
+
+    async fn download_asset(
+        &self,
+        _release: &HostRelease,
+        asset: &HostAsset,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(fs::read(&asset.id)?)
+    }
+
+    async fn delete_asset(
+        &self,
+        _release: &HostRelease,
+        asset: &HostAsset,
+    ) -> Result<(), Box<dyn Error>> {
+        let path = PathBuf::from(&asset.id);
+        let targets = if asset.complete {
+            vec![path.clone(), sig_path(&path)]
+        } else {
+            vec![partial_path(&path), partial_path(&sig_path(&path))]
+        };
+        for target in targets {
+            match fs::remove_file(&target) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Only removes the directory once everything in it has been deleted
+    async fn delete_release(&self, release: &HostRelease) -> Result<(), Box<dyn Error>> {
+        match fs::remove_dir(self.dir.path(&release.id)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// The manifest as <root>/<channel>/latest.json, revisioned by a hash of its contents
+pub struct LocalManifestStore {
+    dir: LocalDir,
+}
+
+impl LocalManifestStore {
+    pub fn new(dir: LocalDir) -> Self {
+        LocalManifestStore { dir }
+    }
+}
+
+#[async_trait(?Send)]
+impl ManifestStore for LocalManifestStore {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn endpoint(&self) -> String {
+        self.dir.url(&self.dir.relative(MANIFEST_FILENAME))
+    }
+
+    async fn read(&self) -> Result<Option<(GistContent, String)>, Box<dyn Error>> {
+        let path = self.dir.path(&self.dir.relative(MANIFEST_FILENAME));
+        match fs::read(&path) {
+            Ok(contents) => Ok(Some((
+                serde_json::from_slice(&contents)?,
+                hex::encode(Sha256::digest(&contents)),
+            ))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn write(&self, manifest: &GistContent, revision: &str) -> Result<bool, Box<dyn Error>> {
+        let current = self.read().await?.map(|(_, revision)| revision);
+        if current.unwrap_or_default() != revision {
+            return Ok(false);
+        }
+        let path = self.dir.path(&self.dir.relative(MANIFEST_FILENAME));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomically(&path, &serde_json::to_vec_pretty(manifest)?)?;
+        Ok(true)
+    }
+}
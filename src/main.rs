@@ -3,26 +3,252 @@ use std::collections::HashMap;
 use std::io::{self};
 use std::process::Command;
 use std::{env, fs, path::Path};
+#[macro_use]
+mod redact;
+use redact::{mask, register_secret};
 mod utilities;
 use utilities::UpdateType;
 use utilities::{
-    create_default_config_if_not_exists, read_and_update_version, read_config, read_tauri_config,
-    read_value, reset_version_in_config, update_entry_in_config,
+    create_default_config_if_not_exists, patch_json_file, read_and_update_version,
+    read_tauri_config, read_value, reset_version_in_config, update_entry_in_config,
+    update_tauri_config_endpoint,
 };
 mod github;
-use github::{
-    create_and_upload_gist, fetch_and_update_gist, get_matching_release, upload_release_asset,
+mod journal;
+use journal::{find_incomplete_journal, ReleaseJournal, RemoteObject};
+mod rollback;
+use rollback::{release_markers, rollback_remote_objects};
+mod gitea;
+mod gitlab;
+mod release_host;
+use release_host::{
+    acquire_release_marker, create_release_host, validate_asset_url, AssetUrlStrategy,
+};
+mod run_lock;
+use run_lock::{acquire_run_lock, release_run_lock, LockInfo};
+mod cli;
+use cli::CliArgs;
+use github::{create_and_upload_gist, migrate_manifest, update_legacy_manifests};
+mod manifest;
+use manifest::{
+    create_manifest_store, publish_platform_release, GistContent, PlatformDetail, UpdatePolicy,
+};
+mod local;
+mod s3;
+mod serve;
+use serve::{serve, ReleaseSource};
+mod rollout;
+use rollout::{update_rollout, Rollout};
+mod proxy;
+use proxy::run_proxy;
+mod config;
+mod doctor;
+use doctor::run_doctor;
+mod init;
+use init::run_init;
+mod keys;
+use keys::{finish_rotation, list_keys, pending_bridge_release, record_signature, start_rotation};
+mod error;
+mod secrets;
+use config::{LayeredConfig, PROJECT_CONFIG};
+use error::{report, JavelinError};
+use secrets::{
+    migrate_global_secrets, migrate_secrets, plaintext_secrets, read_secret, resolve_secrets,
 };
-use github::{GistContent, PlatformDetail};
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() {
+    // Errors are printed through the redacting eprintln rather than the default Debug output
+    if let Err(e) = run().await {
+        std::process::exit(report("Error", e.as_ref()));
+    }
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error>> {
     let base_dir = if cfg!(debug_assertions) { ".." } else { "." };
     println!("\nJAVELIN\n");
     println!("Auto Updater for TAURI");
     println!("-----------------------\n");
     println!("{}", &base_dir);
 
+    let cli = CliArgs::parse();
+
+    // 'javelin serve [--port 8080] [--dir publish/stable]' runs the dynamic update server, it
+    // needs neither a Tauri project nor the config
+    if cli.command() == Some("serve") {
+        let source = match cli.flag_value("dir") {
+            Some(dir) => ReleaseSource::PublishDir(shellexpand::tilde(dir).into_owned().into()),
+            None => ReleaseSource::Journals,
+        };
+        let address = format!("0.0.0.0:{}", cli.flag_value("port").unwrap_or("8080"));
+        if let Err(e) = serve(&address, source, cli.flag_value("profile")).await {
+            std::process::exit(report("Error serving updates", e.as_ref()));
+        }
+        return Ok(());
+    }
+
+    // 'javelin rollout <status|advance|pause|resume|halt> <version>' manages a staged rollout
+    if cli.command() == Some("rollout") {
+        let (Some(action), Some(version)) = (cli.arg(0), cli.arg(1)) else {
+            eprintln!("Usage: javelin rollout <status|advance|pause|resume|halt> <version>");
+            std::process::exit(1);
+        };
+        if let Err(e) = update_rollout(action, version, cli.flag_value("profile")) {
+            std::process::exit(report("Error updating rollout", e.as_ref()));
+        }
+        return Ok(());
+    }
+
+    // 'javelin migrate-secrets' moves plaintext credentials out of javelin.conf.json and the
+    // global config.toml
+    if cli.command() == Some("migrate-secrets") {
+        let mut any_migrated = false;
+        for (file, result) in [
+            (PROJECT_CONFIG.to_string(), migrate_secrets(PROJECT_CONFIG)),
+            (
+                "the global config.toml".to_string(),
+                migrate_global_secrets(),
+            ),
+        ] {
+            match result {
+                Ok(migrated) => {
+                    for key in &migrated {
+                        println!("Moved {} in {} to a file: reference", key, file);
+                    }
+                    any_migrated |= !migrated.is_empty();
+                }
+                Err(e) => {
+                    std::process::exit(report(
+                        &format!("Error migrating secrets in {}", file),
+                        e.as_ref(),
+                    ));
+                }
+            }
+        }
+        if !any_migrated {
+            println!("No plaintext secrets to migrate");
+        }
+        return Ok(());
+    }
+
+    // 'javelin config show [--origin]' prints the merged config and where each value came from
+    if cli.command() == Some("config") {
+        if cli.arg(0) != Some("show") {
+            eprintln!("Usage: javelin config show [--origin] [--set key=value]");
+            std::process::exit(1);
+        }
+        LayeredConfig::load(PROJECT_CONFIG, cli.flag_value("profile"), &cli)?
+            .show(cli.has_flag("origin"));
+        return Ok(());
+    }
+
+    // 'javelin doctor' checks the whole release setup without changing anything
+    if cli.command() == Some("doctor") {
+        if !run_doctor(base_dir, cli.flag_value("profile"), &cli).await? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // 'javelin init' sets up a fresh Tauri project for its first auto updating release
+    if cli.command() == Some("init") {
+        run_init(base_dir).await?;
+        return Ok(());
+    }
+
+    // 'javelin keys list' shows which key signed which release. 'javelin keys rotate' generates a
+    // new key and carries on into the release below, which becomes the bridge release
+    if cli.command() == Some("keys") {
+        match cli.arg(0) {
+            Some("list") => {
+                list_keys()?;
+                return Ok(());
+            }
+            Some("rotate") if cli.flag_value("profile").is_some() => {
+                let error = JavelinError::config(
+                    "Keys can't be rotated for a profile",
+                    "Profiles set their own build.pubkey, rotate it by hand",
+                );
+                std::process::exit(report("Error", &error));
+            }
+            Some("rotate") => {
+                let tauri_config_path = format!("{}/src-tauri/tauri.conf.json", base_dir);
+                start_rotation(base_dir, &tauri_config_path)?;
+            }
+            // Switches to the new key once the other platforms have published the bridge release
+            Some("finish") => {
+                let journal = match pending_bridge_release() {
+                    Ok(Some(journal)) => journal,
+                    Ok(None) => {
+                        println!("No key rotation is pending");
+                        return Ok(());
+                    }
+                    Err(e) => std::process::exit(report("Error", e.as_ref())),
+                };
+                let mut config = LayeredConfig::load(PROJECT_CONFIG, None, &cli)?.config()?;
+                if let Err(e) = resolve_secrets(&mut config) {
+                    std::process::exit(report("Error resolving secrets", e.as_ref()));
+                }
+                let store = create_manifest_store(
+                    &config.manifest_host,
+                    &config.github_repo,
+                    &config.github_username,
+                    &config.github_pat,
+                    &config.gist_id,
+                    &config.s3,
+                    &config.local,
+                    &config.manifest_branch,
+                    &config.distribution_repo,
+                    &journal.new_version,
+                )?;
+                if let Err(e) = finish_rotation(
+                    PROJECT_CONFIG,
+                    &journal,
+                    store.as_ref(),
+                    &config.required_platforms,
+                )
+                .await
+                {
+                    std::process::exit(report(
+                        "Error switching to the new signing key",
+                        e.as_ref(),
+                    ));
+                }
+                return Ok(());
+            }
+            _ => {
+                eprintln!("Usage: javelin keys <list|rotate|finish>");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // 'javelin proxy [--port 8080]' serves the manifest and artifacts using the configured PAT
+    if cli.command() == Some("proxy") {
+        let mut config =
+            LayeredConfig::load(PROJECT_CONFIG, cli.flag_value("profile"), &cli)?.config()?;
+        if let Err(e) = resolve_secrets(&mut config) {
+            std::process::exit(report("Error resolving secrets", e.as_ref()));
+        }
+        let store = create_manifest_store(
+            &config.manifest_host,
+            &config.github_repo,
+            &config.github_username,
+            &config.github_pat,
+            &config.gist_id,
+            &config.s3,
+            &config.local,
+            &config.manifest_branch,
+            &config.distribution_repo,
+            "",
+        )?;
+        let address = format!("0.0.0.0:{}", cli.flag_value("port").unwrap_or("8080"));
+        if let Err(e) = run_proxy(&address, store, &config.github_pat, &config.proxy).await {
+            std::process::exit(report("Error running proxy", e.as_ref()));
+        }
+        return Ok(());
+    }
+
     let operating_system = env::consts::OS;
     let architecture = env::consts::ARCH;
 
@@ -34,10 +260,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         ("macos", _) => "darwin-x86_64",
         ("linux", "x86_64") => "linux-x86_64",
         ("windows", "x86_64") => "windows-x86_64",
-        _ => panic!(
-            "Unsupported platform: {}-{}",
-            operating_system, architecture
-        ),
+        _ => {
+            return Err(JavelinError::build(
+                format!(
+                    "Unsupported platform: {}-{}",
+                    operating_system, architecture
+                ),
+                "Releases are built on macOS, Linux x86_64 or Windows x86_64",
+            )
+            .into())
+        }
     };
     println!("Platform Key : {}", platform_key);
 
@@ -46,70 +278,141 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let tauri_config_path = format!("{}/src-tauri/tauri.conf.json", base_dir);
     if !Path::new(&tauri_config_path).exists() {
-        eprintln!("Error: Tauri config file not found at {}, are you in the project root?", &tauri_config_path);
-        std::process::exit(1); // Quit the program with an error code
+        let error = JavelinError::config(
+            format!("Tauri config file not found at {}", &tauri_config_path),
+            "Run javelin from the root of the Tauri project, next to src-tauri",
+        );
+        std::process::exit(report("Error", &error)); // Quit the program with an error code
     }
     let tauri_config = read_tauri_config(&tauri_config_path)?;
 
-    let config_path = "javelin.conf.json"; // Adjust the path as necessary
+    // Held until the run ends so two runs can't bump the same configs at once
+    let _run_lock = match acquire_run_lock(base_dir) {
+        Ok(lock) => lock,
+        Err(e) => {
+            std::process::exit(report("Error", e.as_ref()));
+        }
+    };
+
+    let config_path = PROJECT_CONFIG;
     create_default_config_if_not_exists(config_path)?;
-    let config = read_config(config_path)?;
-    
+    // '--profile <name>' releases with the overrides under profiles.<name>
+    let profile = cli.flag_value("profile");
+    let layered_config = LayeredConfig::load(config_path, profile, &cli)?;
+    let mut config = layered_config.config()?;
+    let plaintext = plaintext_secrets(layered_config.value());
+    if !plaintext.is_empty() {
+        println!(
+            "Warning: {} stored in plaintext, run 'javelin migrate-secrets' to move them out of {}",
+            plaintext.join(", "),
+            config_path
+        );
+    }
+    if let Err(e) = resolve_secrets(&mut config) {
+        std::process::exit(report("Error resolving secrets", e.as_ref()));
+    }
+
     // let public_key = tauri_config.tauri.updater.pubkey;
 
+    let uses_gist = config.uses_gist();
+    let uses_github = config.uses_github();
     let mut github_username = config.github_username;
     let mut github_repo = config.github_repo;
     let mut github_pat = config.github_pat;
     let mut github_gist = config.gist_id;
+    let mut legacy_gist_ids = config.legacy_gist_ids;
     let mut secret_key_location = config.secret_key_location;
     let mut secret_key_password = config.secret_key_password;
+    let required_platforms = config.required_platforms;
+    let rollout_stages = config.rollout_stages;
+    let update_policy = UpdatePolicy::new(&config.blocked_versions, &config.minimum_version);
+    let release_host_kind = config.release_host;
+    let mut gitlab = config.gitlab;
+    let mut gitea = config.gitea;
+    let manifest_host_kind = config.manifest_host;
+    let s3 = config.s3;
+    let local = config.local;
+    let manifest_branch = config.manifest_branch;
+    let distribution_repo = config.distribution_repo;
+    let build = config.build;
+
+    // Artifacts are named after the product name the app is built with
+    let product_name = if build.product_name.is_empty() {
+        tauri_config.package.productName.clone()
+    } else {
+        build.product_name.clone()
+    };
+    // Each profile keeps its own gist
+    let gist_key: Vec<&str> = match profile {
+        Some(profile) => vec!["profiles", profile, "gist_id"],
+        None => vec!["gist_id"],
+    };
 
     let current_version = tauri_config.package.version; // Use the version from tauri_config
     println!("Current Tauri App Version : {}\n", &current_version);
 
-    let gist_empty = github_gist.trim().is_empty();
+    let asset_url_strategy = match AssetUrlStrategy::parse(&config.asset_url, &distribution_repo) {
+        Ok(strategy) => strategy,
+        Err(e) => {
+            let code = report("Error", e.as_ref());
+            exit_with_error!(&tauri_config_path, &current_version, code);
+        }
+    };
 
-    read_value("Git Username", &mut github_username);
-    read_value("Git Repo", &mut github_repo);
-    read_value("Git Gist ID", &mut github_gist);
-    read_value("Git PAT", &mut github_pat);
-    read_value("Signing Secret Key file Path", &mut secret_key_location);
-    read_value("Signing Key Password", &mut secret_key_password);
+    // Only the gist manifest store needs a gist created up front
+    let gist_empty = uses_gist && github_gist.trim().is_empty();
 
-    if let Err(e) = update_entry_in_config(config_path, &["github_username"], &github_username) {
-        eprintln!("Error updating configuration: {}", e);
-        exit_with_error!(&tauri_config_path, &current_version);
-    }
-
-    if let Err(e) = update_entry_in_config(config_path, &["github_repo"], &github_repo) {
-        eprintln!("Error updating configuration: {}", e);
-        exit_with_error!(&tauri_config_path, &current_version);
+    // Only values entered at a prompt are saved to the project config, values set in any other
+    // layer stay where they are
+    let mut prompted: Vec<(&[&str], String)> = Vec::new();
+    if uses_github {
+        if read_value("Git Username", &mut github_username)? {
+            prompted.push((&["github_username"], github_username.clone()));
+        }
+        if read_value("Git Repo", &mut github_repo)? {
+            prompted.push((&["github_repo"], github_repo.clone()));
+        }
+        if uses_gist && read_value("Git Gist ID", &mut github_gist)? {
+            prompted.push((&gist_key, github_gist.clone()));
+        }
+        read_secret("Git PAT", "github_pat", &mut github_pat)?;
     }
-
-    if let Err(e) = update_entry_in_config(config_path, &["gist_id"], &github_gist) {
-        eprintln!("Error updating configuration: {}", e);
-        exit_with_error!(&tauri_config_path, &current_version);
+    if read_value("Signing Secret Key file Path", &mut secret_key_location)? {
+        prompted.push((&["secret_key_location"], secret_key_location.clone()));
     }
-
-    if let Err(e) = update_entry_in_config(config_path, &["github_pat"], &github_pat) {
-        eprintln!("Error updating configuration: {}", e);
-        exit_with_error!(&tauri_config_path, &current_version);
+    read_secret(
+        "Signing Key Password",
+        "secret_key_password",
+        &mut secret_key_password,
+    )?;
+
+    if release_host_kind == "gitlab" {
+        if read_value("GitLab Project", &mut gitlab.project)? {
+            prompted.push((&["gitlab", "project"], gitlab.project.clone()));
+        }
+        read_secret("GitLab Token", "gitlab.token", &mut gitlab.token)?;
     }
 
-    if let Err(e) =
-        update_entry_in_config(config_path, &["secret_key_location"], &secret_key_location)
-    {
-        eprintln!("Error updating configuration: {}", e);
-        exit_with_error!(&tauri_config_path, &current_version);
+    if release_host_kind == "gitea" || release_host_kind == "forgejo" {
+        if read_value("Gitea/Forgejo Url", &mut gitea.url)? {
+            prompted.push((&["gitea", "url"], gitea.url.clone()));
+        }
+        if read_value("Gitea/Forgejo Repo (owner/repo)", &mut gitea.repo)? {
+            prompted.push((&["gitea", "repo"], gitea.repo.clone()));
+        }
+        read_secret("Gitea/Forgejo Token", "gitea.token", &mut gitea.token)?;
     }
 
-    if let Err(e) =
-        update_entry_in_config(config_path, &["secret_key_password"], &secret_key_password)
-    {
-        eprintln!("Error updating configuration: {}", e);
-        exit_with_error!(&tauri_config_path, &current_version);
+    for (key_path, value) in &prompted {
+        if let Err(e) = update_entry_in_config(config_path, key_path, value) {
+            let code = report("Error updating configuration", e.as_ref());
+            exit_with_error!(&tauri_config_path, &current_version, code);
+        }
     }
 
+    let mut created_gist: Option<String> = None;
+    // Put back if the gist is rolled back
+    let previous_endpoints = tauri_config.tauri.updater.endpoints.clone();
     if gist_empty {
         // We create a draft placeholder Gist to populate the Tauri config, so the App ships pointing to the right update location
         println!("Github Gist is empty. Performing actions");
@@ -117,6 +420,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let new_platform_detail = PlatformDetail {
             signature: "".to_string(),
             url: "".to_string(),
+            version: None,
         };
 
         let gist_content = GistContent {
@@ -128,6 +432,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 platforms.insert(platform_key.to_string(), new_platform_detail);
                 platforms
             },
+            pending: HashMap::new(),
+            policy: update_policy.clone(),
         };
 
         let gist_id_result = create_and_upload_gist(
@@ -135,7 +441,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             &github_username,
             &github_pat,
             &gist_content,
-            platform_key,
             &tauri_config_path,
         )
         .await;
@@ -143,314 +448,953 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         match gist_id_result {
             Ok(gist_id) => {
                 println!("Gist was successfully created with ID: {}", gist_id);
+                created_gist = Some(gist_id.clone());
                 github_gist = gist_id;
-                let key_path = ["gist_id"];
-                if let Err(e) = update_entry_in_config(config_path, &key_path, &github_gist) {
-                    eprintln!("Error updating configuration: {}", e);
-                    exit_with_error!(&tauri_config_path, &current_version);
+                if let Err(e) = update_entry_in_config(config_path, &gist_key, &github_gist) {
+                    let code = report("Error updating configuration", e.as_ref());
+                    exit_with_error!(&tauri_config_path, &current_version, code);
                 } else {
                     println!("Configuration updated successfully.");
                 }
             }
             Err(e) => {
-                eprintln!("\n\nError creating gist (Check Git credentials): {}", e);
-                exit_with_error!(&tauri_config_path, &current_version);
+                let code = report(
+                    "\n\nError creating gist (Check Git credentials)",
+                    e.as_ref(),
+                );
+                exit_with_error!(&tauri_config_path, &current_version, code);
             }
         }
     }
 
     println!("\n");
     println!("-[Config Settings]-");
+    if let Some(profile) = profile {
+        println!("Profile : {}", profile);
+    }
     println!("Git Username : {}", github_username);
     println!("Git Repo : {}", github_repo);
+    if !distribution_repo.is_empty() {
+        println!("Distribution Repo : {}", distribution_repo);
+    }
     println!("Git Gist ID: {}", github_gist);
-    println!("Git PAT : {}", github_pat);
+    println!("Git PAT : {}", mask(&github_pat));
     println!("Signing Secret Key : {}", secret_key_location);
-    println!("Signing Key Password : {}", secret_key_password);
+    println!("Signing Key Password : {}", mask(&secret_key_password));
+    println!("Required Platforms : {:?}", required_platforms);
+    println!("Asset Url : {:?}", asset_url_strategy);
+    println!("Rollout Stages : {:?}", rollout_stages);
+    println!("Update Policy : {:?}", update_policy);
+
+    // Remote objects created by a failed run are removed unless this is set
+    let keep_on_failure = cli.has_flag("keep-on-failure");
+    // For publish directories synced to the web server after the run, where urls can't resolve yet
+    let skip_url_check = cli.has_flag("skip-url-check");
+
+    // 'javelin migrate-manifest [legacy gist ids...]' merges per platform manifests into one
+    if cli.command() == Some("migrate-manifest") {
+        println!("\nMigrating per platform manifests into {}", github_gist);
+        match migrate_manifest(
+            &github_repo,
+            &github_username,
+            &github_pat,
+            &github_gist,
+            &cli.args,
+            &tauri_config_path,
+        )
+        .await
+        {
+            Ok(manifest) => {
+                println!("Manifest migrated at version {}", manifest.version);
+                for (platform, detail) in &manifest.platforms {
+                    println!("  {} : {:?}", platform, detail.version);
+                }
+                // Installed apps keep polling the gist they were built with until a release moves
+                // them to the shared manifest, so releases keep writing their old files for now
+                for gist_id in &cli.args {
+                    if !legacy_gist_ids.contains(gist_id) {
+                        legacy_gist_ids.push(gist_id.clone());
+                    }
+                }
+                if !legacy_gist_ids.is_empty() {
+                    patch_json_file(
+                        config_path,
+                        &[(&["legacy_gist_ids"], legacy_gist_ids.clone().into())],
+                    )?;
+                    println!("\nApps installed before the migration still check the gists they were built with.");
+                    println!(
+                        "{} saved to legacy_gist_ids in {}, each release also updates its platform's manifest in them.",
+                        legacy_gist_ids.join(", "),
+                        config_path
+                    );
+                    println!("Apps updating to that release switch to the shared manifest. Once every platform has released and users have updated, remove legacy_gist_ids.");
+                }
+                return Ok(());
+            }
+            Err(e) => {
+                release_run_lock();
+                std::process::exit(report("Error migrating manifest", e.as_ref()));
+            }
+        }
+    }
+
+    let github_user_repo = format!("{}/{}", github_username, github_repo);
+    let release_host = match create_release_host(
+        &release_host_kind,
+        &github_user_repo,
+        &distribution_repo,
+        &github_pat,
+        &gitlab,
+        &gitea,
+        &s3,
+        &local,
+    ) {
+        Ok(host) => host,
+        Err(e) => {
+            let code = report("Error", e.as_ref());
+            exit_with_error!(&tauri_config_path, &current_version, code);
+        }
+    };
+    // The latest.json asset lives on the Github release being published
+    if manifest_host_kind == "release" && release_host.name() != "github" {
+        let error = JavelinError::config(
+            "manifest_host 'release' needs release_host 'github'",
+            "Publish the manifest somewhere else, e.g. manifest_host 'gist' or 'branch'",
+        );
+        let code = report("Error", &error);
+        exit_with_error!(&tauri_config_path, &current_version, code);
+    }
+    let rollback = (
+        release_host.as_ref(),
+        github_pat.as_str(),
+        config_path,
+        keep_on_failure,
+    );
 
     println!("\n");
     println!("-[Tauri Config]-");
-    println!("Product Name : {:?}", tauri_config.package.productName);
+    println!("Product Name : {:?}", product_name);
     println!("Version : {}", &current_version);
+    println!(
+        "Updater Endpoints : {:?}",
+        tauri_config.tauri.updater.endpoints
+    );
+    println!("Updater Pubkey : {}", tauri_config.tauri.updater.pubkey);
     println!("\n");
 
-    println!("Enter update type (number):\n[1] Major\n[2] Minor\n[3] Patch\n[4] Current\n[q] Quit");
-    let mut update_type_str = String::new();
-    io::stdin()
-        .read_line(&mut update_type_str)
-        .expect("Failed to read line");
-    let update_type = match update_type_str.trim().to_lowercase().as_str() {
-        "1" => UpdateType::Major,
-        "2" => UpdateType::Minor,
-        "3" => UpdateType::Patch,
-        "4" => UpdateType::Current,
-        "q" => std::process::exit(1),
-        _ => {
-            println!("Invalid update type. Please enter 'major', 'minor', or 'patch'.");
-            return Ok(()); // Correctly return from the function
+    // 'javelin resume [version]' continues the latest incomplete release from its journal
+    let resuming = cli.command() == Some("resume");
+
+    let mut journal = if resuming {
+        let journal = match find_incomplete_journal(platform_key, cli.arg(0), profile) {
+            Ok(journal) => journal,
+            Err(e) => {
+                release_run_lock();
+                std::process::exit(report("Error resuming release", e.as_ref()));
+            }
+        };
+        println!(
+            "Resuming release {} for {} from step : {}",
+            journal.new_version,
+            journal.platform_key,
+            journal.next_step()
+        );
+        // Failed runs reset the version, so put the release version back before building
+        if let Err(e) = reset_version_in_config(&tauri_config_path, &journal.new_version) {
+            let code = report("Error resuming release", e.as_ref());
+            exit_with_error!(&tauri_config_path, &journal.previous_version, code);
         }
+        journal
+    } else {
+        println!(
+            "Enter update type (number):\n[1] Major\n[2] Minor\n[3] Patch\n[4] Current\n[q] Quit"
+        );
+        let mut update_type_str = String::new();
+        io::stdin().read_line(&mut update_type_str)?;
+        let update_type = match update_type_str.trim().to_lowercase().as_str() {
+            "1" => UpdateType::Major,
+            "2" => UpdateType::Minor,
+            "3" => UpdateType::Patch,
+            "4" => UpdateType::Current,
+            "q" => {
+                release_run_lock();
+                std::process::exit(1)
+            }
+            _ => {
+                println!("Invalid update type. Please enter 'major', 'minor', or 'patch'.");
+                return Ok(()); // Correctly return from the function
+            }
+        };
+
+        println!(
+            "Please type your update notes for the {:?} update",
+            &update_type
+        );
+        let mut update_notes_str = String::new();
+        io::stdin().read_line(&mut update_notes_str)?;
+
+        // Trim the input and check if it's empty
+        let update_notes_str = update_notes_str.trim();
+        let update_notes_str = if update_notes_str.is_empty() {
+            // If the input is empty, use a default value
+            "Routine bug fixes and performance updates"
+        } else {
+            // If the input is not empty, use the input value
+            update_notes_str
+        };
+        // Use `update_notes_str` as needed from here
+        println!("Update notes: {}", update_notes_str);
+        println!("--------");
+
+        let new_version = read_and_update_version(&tauri_config_path, update_type)?;
+        if update_policy.is_blocked(&new_version) {
+            let error = JavelinError::config(
+                format!("Version {} is in blocked_versions", new_version),
+                "Choose another update type, or take the version out of blocked_versions",
+            );
+            let code = report("Error", &error);
+            exit_with_error!(&tauri_config_path, &current_version, code);
+        }
+
+        let mut journal = ReleaseJournal::new(
+            platform_key,
+            &current_version,
+            &new_version,
+            update_notes_str,
+        );
+        journal.profile = profile.map(str::to_string);
+        journal.version_bumped = true;
+        if !rollout_stages.is_empty() {
+            journal.rollout = Some(Rollout::new(&rollout_stages));
+        }
+        or_exit_with_error!(
+            journal.save(),
+            "Error saving release journal",
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback
+        );
+        journal
     };
 
-    println!(
-        "Please type your update notes for the {:?} update",
-        &update_type
-    );
-    let mut update_notes_str = String::new();
-    io::stdin()
-        .read_line(&mut update_notes_str)
-        .expect("Failed to read line");
-
-    // Trim the input and check if it's empty
-    let update_notes_str = update_notes_str.trim();
-    let update_notes_str = if update_notes_str.is_empty() {
-        // If the input is empty, use a default value
-        "Routine bug fixes and performance updates"
-    } else {
-        // If the input is not empty, use the input value
-        update_notes_str
+    // Failures reset tauri.conf.json to the version from before this release
+    let current_version = journal.previous_version.clone();
+
+    if let Some(id) = created_gist {
+        journal.created.push(RemoteObject::Gist {
+            id,
+            key_path: gist_key.iter().map(|key| key.to_string()).collect(),
+            tauri_config_path: tauri_config_path.clone(),
+            endpoints: Some(previous_endpoints),
+        });
+        or_exit_with_error!(
+            journal.save(),
+            "Error saving release journal",
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback
+        );
+    }
+    let manifest_store = match create_manifest_store(
+        &manifest_host_kind,
+        &github_repo,
+        &github_username,
+        &github_pat,
+        &github_gist,
+        &s3,
+        &local,
+        &manifest_branch,
+        &distribution_repo,
+        &journal.new_version,
+    ) {
+        Ok(store) => store,
+        Err(e) => {
+            let code = report("Error", e.as_ref());
+            exit_with_error!(
+                &tauri_config_path,
+                &current_version,
+                &mut journal,
+                rollback,
+                code
+            );
+        }
     };
-    // Use `update_notes_str` as needed from here
-    println!("Update notes: {}", update_notes_str);
-    println!("--------");
 
-    let new_version = read_and_update_version(&tauri_config_path, update_type)?;
+    // The app has to ship pointing at wherever the manifest is published. Profiles only point the
+    // build at their endpoint, see below
+    let manifest_endpoint = manifest_store.endpoint();
+    if !journal.built
+        && profile.is_none()
+        && !tauri_config
+            .tauri
+            .updater
+            .endpoints
+            .contains(&manifest_endpoint)
+    {
+        println!("Pointing the updater at {}", manifest_endpoint);
+        if let Err(e) = update_tauri_config_endpoint(&tauri_config_path, &manifest_endpoint) {
+            let code = report("Error updating tauri config endpoint", e.as_ref());
+            exit_with_error!(
+                &tauri_config_path,
+                &current_version,
+                &mut journal,
+                rollback,
+                code
+            );
+        }
+    }
 
-    #[allow(unused_assignments)]
-    let mut sig_content = String::new();
+    let new_version = journal.new_version.clone();
+    let update_notes_str = journal.notes.clone();
+    println!("Release journal : {}", journal.path().display());
 
-    // Attempt to expand the home directory in the path
+    if !journal.built {
+        // Attempt to expand the home directory in the path
 
-    println!("\nResolving Secret Key:");
+        println!("\nResolving Secret Key:");
 
-    let secret_key_path = match operating_system {
-        "macos" | "linux" => shellexpand::tilde(&secret_key_location).into_owned(),
-        "windows" => secret_key_location.clone(),
-        _ => panic!("Unsupported platform"),
-    };
+        let secret_key_path = match operating_system {
+            "windows" => secret_key_location.clone(),
+            _ => shellexpand::tilde(&secret_key_location).into_owned(),
+        };
 
-    println! {"Secret Key PATH set as : {}",&secret_key_path};
+        println! {"Secret Key PATH set as : {}",&secret_key_path};
 
-    let secret_key_content =
-        fs::read_to_string(secret_key_path).expect("Failed to read secret key file");
+        let secret_key_content = match fs::read_to_string(&secret_key_path) {
+            Ok(content) => content,
+            Err(e) => {
+                let error = JavelinError::signing(
+                    format!("Can't read the secret key {}: {}", secret_key_path, e),
+                    "Point secret_key_location at the .key file from 'tauri signer generate', or run 'javelin init'",
+                );
+                let code = report("Error", &error);
+                exit_with_error!(
+                    &tauri_config_path,
+                    &current_version,
+                    &mut journal,
+                    rollback,
+                    code
+                );
+            }
+        };
 
-    env::set_var("TAURI_PRIVATE_KEY", secret_key_content.trim());
-    env::set_var("TAURI_KEY_PASSWORD", secret_key_password);
+        register_secret(&secret_key_content);
+        env::set_var("TAURI_PRIVATE_KEY", secret_key_content.trim());
+        env::set_var("TAURI_KEY_PASSWORD", &secret_key_password);
 
-    // Retrieving and printing the environment variable to validate it
-    match env::var("TAURI_PRIVATE_KEY") {
-        Ok(value) => {
-            let first_five = value.chars().take(5).collect::<String>();
-            println!("TAURI_PRIVATE_KEY is set to: {}**********", first_five);
+        // Retrieving and printing the environment variable to validate it
+        match env::var("TAURI_PRIVATE_KEY") {
+            Ok(value) => {
+                let first_five = value.chars().take(5).collect::<String>();
+                println!("TAURI_PRIVATE_KEY is set to: {}**********", first_five);
+            }
+            Err(e) => println!("Couldn't read TAURI_PRIVATE_KEY: {}", e),
         }
-        Err(e) => println!("Couldn't read TAURI_PRIVATE_KEY: {}", e),
-    }
 
-    println!("\nStarting build");
+        // Profile specific values are patched into tauri.conf.json for the build only
+        let mut tauri_patches: Vec<(&[&str], serde_json::Value)> = Vec::new();
+        if !build.product_name.is_empty() {
+            tauri_patches.push((&["package", "productName"], product_name.clone().into()));
+        }
+        if !build.identifier.is_empty() {
+            tauri_patches.push((
+                &["tauri", "bundle", "identifier"],
+                build.identifier.clone().into(),
+            ));
+        }
+        if !build.pubkey.is_empty() {
+            tauri_patches.push((&["tauri", "updater", "pubkey"], build.pubkey.clone().into()));
+        }
+        if profile.is_some() {
+            println!("Pointing the updater at {}", manifest_endpoint);
+            tauri_patches.push((
+                &["tauri", "updater", "endpoints"],
+                vec![manifest_endpoint.clone()].into(),
+            ));
+        }
+        let original_tauri_config = if tauri_patches.is_empty() {
+            None
+        } else {
+            match patch_json_file(&tauri_config_path, &tauri_patches) {
+                Ok(original) => Some(original),
+                Err(e) => {
+                    let code = report("Error patching tauri config", e.as_ref());
+                    exit_with_error!(
+                        &tauri_config_path,
+                        &current_version,
+                        &mut journal,
+                        rollback,
+                        code
+                    );
+                }
+            }
+        };
 
-    let current_dir = env::current_dir()?;
+        let mut build_args = Vec::new();
+        if !build.features.is_empty() {
+            build_args.push("--features".to_string());
+            build_args.push(build.features.join(","));
+        }
+        build_args.extend(build.args.iter().cloned());
 
-    let output = if cfg!(target_os = "windows") {
-        println!("Os Check : Windows");
-        println!("Building. This may take some time");
-        // On Windows, use `cmd /c` to run `npm run tauri build`
-        Command::new("cmd")
-            .args(["/C", "npm run tauri", "build"])
-            .current_dir(base_dir)
-            .output()?
-    } else {
-        println!("Os Check : MacOs or Linux");
-        println!("Building. This may take some time");
-
-        // Directly use `tauri` command on other operating systems
-        Command::new("tauri")
-            .arg("build")
-            .current_dir(base_dir)
-            .output()?
-    };
+        println!("\nStarting build");
+
+        let current_dir = or_exit_with_error!(
+            env::current_dir(),
+            "Error reading the working directory",
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback
+        );
+
+        let output = if cfg!(target_os = "windows") {
+            println!("Os Check : Windows");
+            println!("Building. This may take some time");
+            // On Windows, use `cmd /c` to run `npm run tauri build`, npm passes on arguments after "--"
+            let mut args = vec![
+                "/C".to_string(),
+                "npm run tauri".to_string(),
+                "build".to_string(),
+            ];
+            if !build_args.is_empty() {
+                args.push("--".to_string());
+                args.extend(build_args);
+            }
+            Command::new("cmd")
+                .args(args)
+                .current_dir(base_dir)
+                .output()
+        } else {
+            println!("Os Check : MacOs or Linux");
+            println!("Building. This may take some time");
+
+            // Directly use `tauri` command on other operating systems
+            Command::new("tauri")
+                .arg("build")
+                .args(build_args)
+                .current_dir(base_dir)
+                .output()
+        };
+
+        if let Some(original) = &original_tauri_config {
+            if let Err(e) = fs::write(&tauri_config_path, original) {
+                eprintln!("Error restoring tauri config: {}", e);
+            }
+        }
+        let output = or_exit_with_error!(
+            output,
+            "Error running the build",
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback
+        );
 
-    if output.status.success() {
-        let _stdout = String::from_utf8_lossy(&output.stdout);
-        // println!("\nBuild Success: {}\n", stdout);
-        println!("\nBuild Success!\n");
+        if output.status.success() {
+            let _stdout = String::from_utf8_lossy(&output.stdout);
+            // println!("\nBuild Success: {}\n", stdout);
+            println!("\nBuild Success!\n");
+            journal.built = true;
+            or_exit_with_error!(
+                journal.save(),
+                "Error saving release journal",
+                &tauri_config_path,
+                &current_version,
+                &mut journal,
+                rollback
+            );
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // The updater signs the bundle as part of the build, a bad key or password fails it
+            let lowercase = stderr.to_lowercase();
+            let error = if lowercase.contains("password") || lowercase.contains("secret key") {
+                JavelinError::signing(
+                    format!("Signing the bundle failed: {}", stderr.trim()),
+                    "Check secret_key_password is the password of the key at secret_key_location, 'javelin doctor' tests them",
+                )
+            } else {
+                JavelinError::build(
+                    stderr.trim(),
+                    "Fix the build error above, then run 'javelin resume' to continue the release",
+                )
+            };
+            let code = report("\nError during build process", &error);
+            exit_with_error!(
+                &tauri_config_path,
+                &current_version,
+                &mut journal,
+                rollback,
+                code
+            );
+        }
+
+        // Change back to the original directory if needed
+        or_exit_with_error!(
+            env::set_current_dir(current_dir),
+            "Error restoring the working directory",
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback
+        );
+    } else {
+        println!("\nBuild already completed, skipping");
+    }
 
+    if journal.signature.is_none() {
         println!("Constructing Signature file PATH");
 
         #[cfg(target_os = "windows")]
         let sig_file_path = format!(
             "{}\\src-tauri\\target\\release\\bundle\\msi\\{}_{}_x64_en-US.msi.zip.sig",
-            &base_dir, tauri_config.package.productName, &new_version
+            &base_dir, product_name, &new_version
         );
 
         #[cfg(target_os = "macos")]
         let sig_file_path = format!(
             "{}/src-tauri/target/release/bundle/macos/{}.app.tar.gz.sig",
-            &base_dir, tauri_config.package.productName
+            &base_dir, product_name
+        );
+
+        #[cfg(target_os = "linux")]
+        let sig_file_path = format!(
+            "{}/src-tauri/target/release/bundle/appimage/{}.AppImage.tar.gz.sig",
+            &base_dir, product_name
         );
 
         println!("Attempting to read Signature file path : {}", sig_file_path);
         // Read the signature file
-        sig_content = fs::read_to_string(&sig_file_path).expect("Failed to read signature file");
+        let sig_content = match fs::read_to_string(&sig_file_path) {
+            Ok(content) => content,
+            Err(e) => {
+                let error = JavelinError::signing(
+                    format!("Can't read the signature {}: {}", sig_file_path, e),
+                    "The build only signs the bundle when tauri.updater.active is true and the secret key is set, check tauri.conf.json and the build output",
+                );
+                let code = report("Error", &error);
+                exit_with_error!(
+                    &tauri_config_path,
+                    &current_version,
+                    &mut journal,
+                    rollback,
+                    code
+                );
+            }
+        };
         println!("Signature file read successfully ");
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        eprintln!("\nError during build process: {}", stderr);
-        println!("Ending operation, please fix the error above");
-        exit_with_error!(&tauri_config_path, &current_version);
+        match record_signature(
+            &new_version,
+            platform_key,
+            &sig_content,
+            &secret_key_location,
+        ) {
+            Ok(key_id) => journal.signing_key_id = key_id,
+            Err(e) => eprintln!("Warning: couldn't record the signing key: {}", e),
+        }
+        journal.signature = Some(sig_content);
+        or_exit_with_error!(
+            journal.save(),
+            "Error saving release journal",
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback
+        );
     }
 
-    // Change back to the original directory if needed
-    env::set_current_dir(current_dir)?;
-
     // At this point we have all required variables and applicaiton is built can begin github api actions
 
-    // Create release
-    println!("\nCreating Release");
-    let operating_system = env::consts::OS;
-    println!("Current Operating System : {}", operating_system);
-    // Construct the path to the signature file // Need to change (remove ../) this after install as CLT
-    let bundle_filepath = match operating_system {
-        "macos" => format!(
-            "{}/src-tauri/target/release/bundle/macos/{}.app.tar.gz",
-            &base_dir, tauri_config.package.productName
-        ),
-        "windows" => format!(
-            "{}\\src-tauri\\target\\release\\bundle\\msi\\{}_{}_x64_en-US.msi.zip",
-            &base_dir, tauri_config.package.productName, &new_version
-        ),
-        "linux" => format!(
-            "{}/src-tauri/target/release/bundle/appimage/{}.AppImage.tar.gz", // Assuming you're using deb for Linux
-            &base_dir, tauri_config.package.productName
-        ),
-        _ => panic!("Unsupported operating system: {}", operating_system),
-    };
-    println!("Bundle filepath: {}", bundle_filepath);
-
-    let new_filepath = match operating_system {
-        "macos" => format!(
-            "{}/src-tauri/target/release/bundle/macos/{}-{}.app.tar.gz",
-            &base_dir, tauri_config.package.productName, platform_key
-        ),
-        "windows" => format!(
-            "{}\\src-tauri\\target\\release\\bundle\\msi\\{}-{}.msi.zip",
-            &base_dir, tauri_config.package.productName, platform_key
-        ),
-        "linux" => format!(
-            "{}/src-tauri/target/release/bundle/appimage/{}-{}.AppImage.tar.gz", // Assuming you're using deb for Linux
-            &base_dir, tauri_config.package.productName, platform_key
-        ),
-        _ => panic!("Unsupported operating system: {}", operating_system),
-    };
-
-    let _asset_filename = match operating_system {
-        "macos" => format!("{}.app.tar.gz", tauri_config.package.productName),
-        "windows" => format!(
-            "{}_{}_x64_en-US.msi.zip",
-            tauri_config.package.productName, new_version
-        ),
-        "linux" => format!(
-            "{}.AppImage.tar.gz", // Assuming you're using deb for Linux
-            tauri_config.package.productName
-        ),
-        _ => panic!("Unsupported operating system: {}", operating_system),
-    };
+    if journal.artifact_path.is_none() {
+        // Create release
+        println!("\nCreating Release");
+        let operating_system = env::consts::OS;
+        println!("Current Operating System : {}", operating_system);
+        // Construct the path to the signature file // Need to change (remove ../) this after install as CLT
+        let bundle_filepath = match operating_system {
+            "macos" => format!(
+                "{}/src-tauri/target/release/bundle/macos/{}.app.tar.gz",
+                &base_dir, product_name
+            ),
+            "windows" => format!(
+                "{}\\src-tauri\\target\\release\\bundle\\msi\\{}_{}_x64_en-US.msi.zip",
+                &base_dir, product_name, &new_version
+            ),
+            "linux" => format!(
+                "{}/src-tauri/target/release/bundle/appimage/{}.AppImage.tar.gz", // Assuming you're using deb for Linux
+                &base_dir, product_name
+            ),
+            _ => {
+                let error = JavelinError::build(
+                    format!("Unsupported operating system: {}", operating_system),
+                    "Releases are built on macOS, Linux or Windows",
+                );
+                let code = report("Error", &error);
+                exit_with_error!(
+                    &tauri_config_path,
+                    &current_version,
+                    &mut journal,
+                    rollback,
+                    code
+                );
+            }
+        };
+        println!("Bundle filepath: {}", bundle_filepath);
+
+        let new_filepath = match operating_system {
+            "macos" => format!(
+                "{}/src-tauri/target/release/bundle/macos/{}-{}.app.tar.gz",
+                &base_dir, product_name, platform_key
+            ),
+            "windows" => format!(
+                "{}\\src-tauri\\target\\release\\bundle\\msi\\{}-{}.msi.zip",
+                &base_dir, product_name, platform_key
+            ),
+            "linux" => format!(
+                "{}/src-tauri/target/release/bundle/appimage/{}-{}.AppImage.tar.gz", // Assuming you're using deb for Linux
+                &base_dir, product_name, platform_key
+            ),
+            _ => {
+                let error = JavelinError::build(
+                    format!("Unsupported operating system: {}", operating_system),
+                    "Releases are built on macOS, Linux or Windows",
+                );
+                let code = report("Error", &error);
+                exit_with_error!(
+                    &tauri_config_path,
+                    &current_version,
+                    &mut journal,
+                    rollback,
+                    code
+                );
+            }
+        };
 
-    // Rename the file
-    fs::rename(&bundle_filepath, &new_filepath).expect("Failed to rename the file");
+        // Rename the file, keeping the signature next to the artifact for hosts that publish it too
+        let bundle_sig = format!("{}.sig", bundle_filepath);
+        let renamed = fs::rename(&bundle_filepath, &new_filepath).and_then(|()| {
+            if Path::new(&bundle_sig).exists() {
+                fs::rename(&bundle_sig, format!("{}.sig", new_filepath))
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(e) = renamed {
+            let error = JavelinError::build(
+                format!("Can't rename the bundle {}: {}", bundle_filepath, e),
+                "Check the build produced the updater bundle: the bundle targets in tauri.conf.json and the product name have to match",
+            );
+            let code = report("Error", &error);
+            exit_with_error!(
+                &tauri_config_path,
+                &current_version,
+                &mut journal,
+                rollback,
+                code
+            );
+        }
 
-    println!("Artifact renamed to: {}", new_filepath);
+        println!("Artifact renamed to: {}", new_filepath);
+        journal.bundle_path = Some(bundle_filepath);
+        journal.artifact_path = Some(new_filepath);
+        or_exit_with_error!(
+            journal.save(),
+            "Error saving release journal",
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback
+        );
+    }
 
+    let new_filepath = journal.artifact_path.clone().unwrap_or_default();
     let filename = Path::new(&new_filepath);
 
-    let github_user_repo = format!("{}/{}", github_username, github_repo);
-
     println!("GitHub User/Repo : {}", github_user_repo);
 
     let release_notes = update_notes_str.trim().to_string();
 
-    println!("Fetching latest release");
-    let release =
-        get_matching_release(&github_user_repo, &new_version, &release_notes, &github_pat).await?;
-    // get_latest_release(&github_user_repo, &new_version, &release_notes, &github_pat).await?;
+    if journal.release.is_none() {
+        println!("Fetching latest release");
+        let release = match release_host
+            .find_or_create_release(&new_version, &release_notes)
+            .await
+        {
+            Ok(release) => release,
+            Err(e) => {
+                let code = report("Error creating release", e.as_ref());
+                exit_with_error!(
+                    &tauri_config_path,
+                    &current_version,
+                    &mut journal,
+                    rollback,
+                    code
+                );
+            }
+        };
+        // get_latest_release(&github_user_repo, &new_version, &release_notes, &github_pat).await?;
+        if release.created {
+            journal.created.push(RemoteObject::Release {
+                release: release.clone(),
+            });
+        }
+        journal.release = Some(release);
+        or_exit_with_error!(
+            journal.save(),
+            "Error saving release journal",
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback
+        );
+    }
+    let Some(release) = journal.release.clone() else {
+        exit_with_error!(
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback,
+            1
+        );
+    };
+
+    // Let runs on other machines know this platform is being published to the release. Markers
+    // in the journal were left by this release's earlier run, which resume may replace.
+    let own_markers: Vec<String> = journal
+        .created
+        .iter()
+        .filter_map(|object| match object {
+            RemoteObject::Marker { asset, .. } => Some(asset.id.clone()),
+            _ => None,
+        })
+        .collect();
+    match acquire_release_marker(
+        release_host.as_ref(),
+        &release,
+        platform_key,
+        &LockInfo::current(),
+        &own_markers,
+    )
+    .await
+    {
+        Ok(asset) => {
+            journal
+                .created
+                .retain(|object| !matches!(object, RemoteObject::Marker { .. }));
+            journal.created.push(RemoteObject::Marker {
+                release: release.clone(),
+                asset,
+            });
+            or_exit_with_error!(
+                journal.save(),
+                "Error saving release journal",
+                &tauri_config_path,
+                &current_version,
+                &mut journal,
+                rollback
+            );
+        }
+        Err(e) => {
+            let code = report("Error marking release in progress", e.as_ref());
+            exit_with_error!(
+                &tauri_config_path,
+                &current_version,
+                &mut journal,
+                rollback,
+                code
+            );
+        }
+    }
 
     // TODO Add check for asset filename in existing release
     // TODO Add fn to delete existing asset if exists - Kept as warning , no real need to replace versions for specific arch
-    println!("Release url : {}", release.upload_url);
-
-    println!("Uploading Release");
-    let release_asset_url =
-        upload_release_asset(&release.upload_url, filename, &github_pat).await?;
+    println!("Release : {} on {}", release.tag, release_host.name());
+
+    if journal.asset_url.is_none() {
+        journal.created.push(RemoteObject::Asset {
+            release: release.clone(),
+            name: filename
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            asset: None,
+        });
+        or_exit_with_error!(
+            journal.save(),
+            "Error saving release journal",
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback
+        );
+        println!("Uploading Release");
+        let release_asset = match release_host.upload_asset(&release, filename, None).await {
+            Ok(asset) => asset,
+            Err(e) => {
+                let code = report("Error uploading release asset", e.as_ref());
+                exit_with_error!(
+                    &tauri_config_path,
+                    &current_version,
+                    &mut journal,
+                    rollback,
+                    code
+                );
+            }
+        };
+        let manifest_url = asset_url_strategy.url_for(&release_asset, &release.tag, platform_key);
+        if let Some(RemoteObject::Asset { asset, .. }) = journal.created.last_mut() {
+            *asset = Some(release_asset);
+        }
+        or_exit_with_error!(
+            journal.save(),
+            "Error saving release journal",
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback
+        );
+        if skip_url_check {
+            println!("Not checking {} resolves", manifest_url);
+        } else if let Err(e) = validate_asset_url(&manifest_url, &github_pat).await {
+            let code = report("Error", e.as_ref());
+            exit_with_error!(
+                &tauri_config_path,
+                &current_version,
+                &mut journal,
+                rollback,
+                code
+            );
+        }
+        journal.asset_url = Some(manifest_url);
+        or_exit_with_error!(
+            journal.save(),
+            "Error saving release journal",
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback
+        );
+    }
 
-    println!("\nResolving Gist Data");
+    println!("\nResolving Manifest Data");
     let current_time = Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
 
     let new_platform_detail = PlatformDetail {
-        signature: sig_content.to_string(),
-        url: release_asset_url.to_string(),
+        signature: journal.signature.clone().unwrap_or_default(),
+        url: journal.asset_url.clone().unwrap_or_default(),
+        version: Some(new_version.to_string()),
     };
 
-    if !github_gist.trim().is_empty() {
-        println!("gist_id exists and is not empty: {}", github_gist);
-        if let Err(e) = fetch_and_update_gist(
-            &github_repo,
-            &github_pat,
-            &github_gist,
+    if journal.manifest_revision.is_some() {
+        println!("Manifest already updated, skipping");
+    } else if !uses_gist || !github_gist.trim().is_empty() {
+        println!("Publishing manifest to {}", manifest_store.endpoint());
+        match publish_platform_release(
+            manifest_store.as_ref(),
             &new_version,
-            update_notes_str,
+            &update_notes_str,
             &current_time,
             platform_key,
             new_platform_detail,
+            &required_platforms,
+            &update_policy,
         )
         .await
         {
-            eprintln!("Error updating gist: {}", e);
-            exit_with_error!(&tauri_config_path, &current_version);
-        } else {
-            println!("Gist updated successfully");
+            Ok(revision) => {
+                println!("Manifest updated successfully");
+                if !legacy_gist_ids.is_empty() {
+                    let legacy = GistContent {
+                        version: new_version.clone(),
+                        notes: update_notes_str.clone(),
+                        pub_date: current_time.clone(),
+                        platforms: HashMap::from([(
+                            platform_key.to_string(),
+                            PlatformDetail {
+                                signature: journal.signature.clone().unwrap_or_default(),
+                                url: journal.asset_url.clone().unwrap_or_default(),
+                                version: None,
+                            },
+                        )]),
+                        pending: HashMap::new(),
+                        policy: update_policy.clone(),
+                    };
+                    match update_legacy_manifests(
+                        &github_repo,
+                        &github_pat,
+                        &legacy_gist_ids,
+                        platform_key,
+                        &legacy,
+                    )
+                    .await
+                    {
+                        Ok(updated) if updated.is_empty() => {}
+                        Ok(updated) => {
+                            println!("Legacy manifest updated in {}", updated.join(", "))
+                        }
+                        Err(e) => eprintln!("Warning: couldn't update the legacy manifests: {}", e),
+                    }
+                }
+                journal.manifest_revision = Some(revision);
+                journal.published_at = Some(current_time.clone());
+                // The manifest offers the release now, rolling it back would break live updates
+                if let Err(e) = journal.save() {
+                    eprintln!(
+                        "Warning: release published but its journal couldn't be saved: {}",
+                        e
+                    );
+                }
+            }
+            Err(e) => {
+                let code = report("Error updating manifest", e.as_ref());
+                exit_with_error!(
+                    &tauri_config_path,
+                    &current_version,
+                    &mut journal,
+                    rollback,
+                    code
+                );
+            }
         }
     } else {
         // Handle the case where gist_id is empty or not set , THIS SHOULD BE REDUNDANT NOW
         // Checks are done at the start so added graceful exit.
-        exit_with_error!(&tauri_config_path, &current_version);
-
-        // println!("gist_id is empty or not set");
-        // let gist_content = GistContent {
-        //     version: new_version.to_string(),
-        //     notes: update_notes_str.trim().to_string(),
-        //     pub_date: current_time,
-        //     platforms: {
-        //         let mut platforms = HashMap::new();
-        //         platforms.insert(platform_key.to_string(), new_platform_detail);
-        //         platforms
-        //     },
-        // };
-
-        // let gist_id_result = create_and_upload_gist(
-        //     &github_repo,
-        //     &github_username,
-        //     &github_pat,
-        //     &gist_content,
-        //     platform_key,
-        //     tauri_config_path,
-        // )
-        // .await;
-
-        // match gist_id_result {
-        //     Ok(gist_id) => {
-        //         println!("Gist was successfully created with ID: {}", gist_id);
-        //         let key_path = ["gist_id"];
-        //         if let Err(e) = update_entry_in_config(config_path, &key_path, &gist_id) {
-        //             eprintln!("Error updating configuration: {}", e);
-        //             exit_with_error!(&tauri_config_path, &current_version);
-        //         } else {
-        //             println!("Configuration updated successfully.");
-        //         }
-        //     }
-        //     Err(e) => {
-        //         eprintln!("Error creating gist: {}", e);
-
-        //         exit_with_error!(&tauri_config_path, &current_version);
-        //     }
-        // }
+        let error = JavelinError::config(
+            "gist_id is empty",
+            "Run 'javelin init' or set gist_id to the manifest gist",
+        );
+        let code = report("Error", &error);
+        exit_with_error!(
+            &tauri_config_path,
+            &current_version,
+            &mut journal,
+            rollback,
+            code
+        );
+    }
+
+    release_markers(&mut journal, release_host.as_ref()).await;
+    journal.completed = true;
+    if let Err(e) = journal.save() {
+        eprintln!(
+            "Warning: release published but its journal couldn't be saved: {}",
+            e
+        );
+    }
+
+    // Completing the bridge release of a key rotation switches to the new key. The release itself
+    // is out either way, so a rotation that can't finish yet is reported without failing the run
+    if let Err(e) = finish_rotation(
+        config_path,
+        &journal,
+        manifest_store.as_ref(),
+        &required_platforms,
+    )
+    .await
+    {
+        report("Error switching to the new signing key", e.as_ref());
     }
 
     println!("Updated Version to : {:?}", new_version.to_string());
@@ -0,0 +1,526 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::JavelinError;
+use crate::github::{GistManifestStore, ReleaseAssetManifestStore, RepoManifestStore};
+use crate::local::{LocalDir, LocalManifestStore};
+use crate::s3::{S3Client, S3ManifestStore};
+use crate::utilities::{LocalConfig, ManifestBranchConfig, S3Config};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct GistContent {
+    pub version: String,
+    pub notes: String,
+    pub pub_date: String,
+    pub platforms: HashMap<String, PlatformDetail>,
+    // Entries of platforms that published a version the top level hasn't moved to yet. They're
+    // promoted into "platforms" with the version bump, so clients are never offered an artifact
+    // under another version's number. The Tauri updater ignores this field.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub pending: HashMap<String, PlatformDetail>,
+    // Extra top level fields the Tauri updater ignores, for apps that enforce the policy themselves
+    #[serde(flatten)]
+    pub policy: UpdatePolicy,
+}
+
+// Versions clients must not stay on. Clients running a blocked version, or one older than the
+// minimum, are always offered an update and never offered a blocked version.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct UpdatePolicy {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocked_versions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minimum_version: Option<String>,
+}
+
+impl UpdatePolicy {
+    pub fn new(blocked_versions: &[String], minimum_version: &str) -> Self {
+        UpdatePolicy {
+            blocked_versions: blocked_versions
+                .iter()
+                .map(|version| version.trim().to_string())
+                .filter(|version| !version.is_empty())
+                .collect(),
+            minimum_version: Some(minimum_version.trim().to_string())
+                .filter(|version| !version.is_empty()),
+        }
+    }
+
+    pub fn is_blocked(&self, version: &str) -> bool {
+        let key = version_key(version);
+        self.blocked_versions
+            .iter()
+            .any(|blocked| version_key(blocked) == key)
+    }
+
+    // Whether a client on this version has to update
+    pub fn requires_update(&self, version: &str) -> bool {
+        self.is_blocked(version)
+            || self
+                .minimum_version
+                .as_deref()
+                .is_some_and(|minimum| version_key(version) < version_key(minimum))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlatformDetail {
+    pub signature: String,
+    pub url: String,
+    // Version this platform last published, the Tauri updater ignores this field
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+// Somewhere the updater manifest lives. Reads return the manifest along with an opaque revision
+// that changes on every write, or None if no manifest has been written yet.
+#[async_trait(?Send)]
+pub trait ManifestStore {
+    fn name(&self) -> &'static str;
+
+    // Url the Tauri updater should fetch the manifest from
+    fn endpoint(&self) -> String;
+
+    async fn read(&self) -> Result<Option<(GistContent, String)>, Box<dyn Error>>;
+
+    // Writes the manifest if it's still at the revision it was read at ("" when there was none),
+    // returning false without writing when another runner changed it in the meantime
+    async fn write(&self, manifest: &GistContent, revision: &str) -> Result<bool, Box<dyn Error>>;
+
+    // Whether write() is a real compare and swap. Other stores check the revision just before
+    // writing, which leaves a short window for a concurrent write to be lost.
+    fn conditional_writes(&self) -> bool {
+        false
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_manifest_store(
+    kind: &str,
+    github_repo: &str,
+    github_username: &str,
+    github_pat: &str,
+    gist_id: &str,
+    s3: &S3Config,
+    local: &LocalConfig,
+    manifest_branch: &ManifestBranchConfig,
+    distribution_repo: &str,
+    release_tag: &str,
+) -> Result<Box<dyn ManifestStore>, Box<dyn Error>> {
+    // Releases live in the distribution repo when one is configured
+    let release_repo = if distribution_repo.trim().is_empty() {
+        format!("{}/{}", github_username, github_repo)
+    } else {
+        distribution_repo.trim().to_string()
+    };
+    match kind {
+        "" | "gist" => Ok(Box::new(GistManifestStore::new(
+            github_repo,
+            github_username,
+            github_pat,
+            gist_id,
+        ))),
+        "branch" => {
+            let repo = if manifest_branch.repo.trim().is_empty() {
+                format!("{}/{}", github_username, github_repo)
+            } else {
+                manifest_branch.repo.trim().to_string()
+            };
+            Ok(Box::new(RepoManifestStore::new(
+                &repo,
+                &manifest_branch.branch,
+                &manifest_branch.path,
+                &manifest_branch.url,
+                github_pat,
+            )))
+        }
+        "release" => Ok(Box::new(ReleaseAssetManifestStore::new(
+            &release_repo,
+            release_tag,
+            github_pat,
+        ))),
+        "s3" => Ok(Box::new(S3ManifestStore::new(S3Client::new(s3)?))),
+        "local" => Ok(Box::new(LocalManifestStore::new(LocalDir::new(local)?))),
+        _ => Err(JavelinError::config(
+            format!("Unknown manifest_host '{}'", kind),
+            "Set manifest_host to 'gist', 'branch', 'release', 's3' or 'local'",
+        )
+        .into()),
+    }
+}
+
+// Sort key for a version string, e.g. "v1.2.10-beta" orders as [1, 2, 10]
+pub fn version_key(version: &str) -> Vec<u32> {
+    version
+        .trim()
+        .trim_start_matches('v')
+        .split('.')
+        .map(|segment| {
+            let digits: String = segment.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse::<u32>().unwrap_or(0)
+        })
+        .collect()
+}
+
+// Records a platform release in the manifest. The top level version (and notes) only move once
+// every required platform has published the new version, otherwise clients on platforms that
+// haven't published yet would be offered an update that doesn't exist for them. Until then the
+// entry waits under "pending", so this platform's clients keep getting the released artifact.
+pub fn merge_platform_release(
+    manifest: &mut GistContent,
+    platform_key: &str,
+    mut platform_detail: PlatformDetail,
+    new_version: &str,
+    new_notes: &str,
+    new_pub_date: &str,
+    required_platforms: &[String],
+) -> bool {
+    platform_detail.version = Some(new_version.to_string());
+    if manifest.version == new_version {
+        // The version is already out, e.g. a platform publishing after the required ones
+        manifest.pending.remove(platform_key);
+        manifest
+            .platforms
+            .insert(platform_key.to_string(), platform_detail);
+        return true;
+    }
+    manifest
+        .pending
+        .insert(platform_key.to_string(), platform_detail);
+
+    let published = |key: &str| {
+        manifest
+            .pending
+            .get(key)
+            .and_then(|detail| detail.version.as_deref())
+            == Some(new_version)
+    };
+
+    // With no required platforms configured, every platform present in the manifest is required
+    let all_published = if required_platforms.is_empty() {
+        manifest
+            .platforms
+            .keys()
+            .chain(manifest.pending.keys())
+            .all(|key| published(key))
+    } else {
+        required_platforms.iter().all(|key| published(key))
+    };
+
+    if all_published {
+        let new_key = version_key(new_version);
+        for (key, detail) in std::mem::take(&mut manifest.pending) {
+            let pending_key = version_key(detail.version.as_deref().unwrap_or_default());
+            if pending_key == new_key {
+                manifest.platforms.insert(key, detail);
+            } else if pending_key > new_key {
+                // Already waiting on a newer version
+                manifest.pending.insert(key, detail);
+            }
+        }
+        manifest.version = new_version.to_string();
+        manifest.notes = new_notes.to_string();
+        manifest.pub_date = new_pub_date.to_string();
+    }
+
+    all_published
+}
+
+// This platform's entry for a version, whether released or still pending
+pub fn platform_entry<'a>(
+    manifest: &'a GistContent,
+    platform_key: &str,
+    version: &str,
+) -> Option<&'a PlatformDetail> {
+    [&manifest.platforms, &manifest.pending]
+        .into_iter()
+        .filter_map(|entries| entries.get(platform_key))
+        .find(|detail| detail.version.as_deref() == Some(version))
+}
+
+const MANIFEST_UPDATE_ATTEMPTS: u32 = 6;
+const MANIFEST_VERIFY_DELAY_MS: u64 = 5000;
+
+// Exponential backoff with a little jitter so parallel runners don't retry in lockstep
+async fn backoff(attempt: u32) {
+    let jitter = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::from(d.subsec_millis()))
+        .unwrap_or(0)
+        % 250;
+    let delay = 500 * 2u64.pow(attempt) + jitter;
+    println!("Retrying manifest update in {}ms", delay);
+    tokio::time::sleep(Duration::from_millis(delay)).await;
+}
+
+// Stores with conditional writes (branch, s3) only replace the manifest if it's still at the
+// revision read, so every merge builds on the previous write. Gists and the others are guarded
+// best effort: the revision is checked right before writing and, after a delay, the manifest is
+// read again to make sure no runner that passed the same check wrote over this entry. Either way
+// a lost race re-reads, re-merges and writes again after a backoff.
+// Returns the manifest revision the platform entry was confirmed at.
+#[allow(clippy::too_many_arguments)]
+pub async fn publish_platform_release(
+    store: &dyn ManifestStore,
+    new_version: &str,
+    new_notes: &str,
+    new_pub_date: &str,
+    platform_key: &str,
+    new_platform_detail: PlatformDetail,
+    required_platforms: &[String],
+    policy: &UpdatePolicy,
+) -> Result<String, Box<dyn Error>> {
+    for attempt in 0..MANIFEST_UPDATE_ATTEMPTS {
+        if attempt > 0 {
+            backoff(attempt - 1).await;
+        }
+
+        let (mut existing_content, revision) = store.read().await?.unwrap_or_default();
+
+        let released = merge_platform_release(
+            &mut existing_content,
+            platform_key,
+            new_platform_detail.clone(),
+            new_version,
+            new_notes,
+            new_pub_date,
+            required_platforms,
+        );
+        existing_content.policy = policy.clone();
+
+        if !store.write(&existing_content, &revision).await? {
+            println!(
+                "Manifest changed while merging (revision {}), merging again",
+                revision
+            );
+            continue;
+        }
+
+        if !store.conditional_writes() {
+            // Long enough for a write that passed the same revision check to land
+            tokio::time::sleep(Duration::from_millis(MANIFEST_VERIFY_DELAY_MS)).await;
+        }
+
+        // Read back to make sure a concurrent write didn't replace our entry
+        let (written_manifest, written_revision) = store.read().await?.ok_or_else(|| {
+            JavelinError::manifest(
+                format!(
+                    "The {} manifest is missing after it was written",
+                    store.name()
+                ),
+                "Check nothing else deletes the manifest, then run 'javelin resume'",
+            )
+        })?;
+        let confirmed = platform_entry(&written_manifest, platform_key, new_version)
+            .is_some_and(|detail| detail.url == new_platform_detail.url);
+
+        if !confirmed {
+            println!(
+                "Platform entry for {} was overwritten by a concurrent update",
+                platform_key
+            );
+            continue;
+        }
+
+        if released {
+            println!(
+                "All required platforms published {}, manifest version updated",
+                new_version
+            );
+        } else {
+            println!(
+                "Manifest version kept at {} until all required platforms publish {}",
+                written_manifest.version, new_version
+            );
+        }
+
+        return Ok(written_revision);
+    }
+
+    Err(JavelinError::manifest(
+        format!(
+            "Failed to update the {} manifest after {} attempts, other runners kept changing it",
+            store.name(),
+            MANIFEST_UPDATE_ATTEMPTS
+        ),
+        "Wait for the other platforms' runs to finish, then run 'javelin resume'",
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detail(url: &str) -> PlatformDetail {
+        PlatformDetail {
+            signature: "sig".to_string(),
+            url: url.to_string(),
+            version: None,
+        }
+    }
+
+    fn manifest_at(version: &str, platforms: &[&str]) -> GistContent {
+        GistContent {
+            version: version.to_string(),
+            notes: "old notes".to_string(),
+            pub_date: "2024-01-01T00:00:00Z".to_string(),
+            platforms: platforms
+                .iter()
+                .map(|key| {
+                    let mut detail = detail(key);
+                    detail.version = Some(version.to_string());
+                    (key.to_string(), detail)
+                })
+                .collect(),
+            pending: HashMap::new(),
+            policy: UpdatePolicy::default(),
+        }
+    }
+
+    #[test]
+    fn version_key_orders_numerically() {
+        assert_eq!(version_key("v1.2.10-beta"), vec![1, 2, 10]);
+        assert!(version_key("1.2.10") > version_key("1.2.9"));
+        assert!(version_key("2.0.0") > version_key("1.99.99"));
+        assert_eq!(version_key(" v1.0.0 "), version_key("1.0.0"));
+    }
+
+    #[test]
+    fn merge_waits_for_required_platforms() {
+        let required = vec!["linux-x86_64".to_string(), "windows-x86_64".to_string()];
+        let mut manifest = manifest_at("1.0.0", &["linux-x86_64", "windows-x86_64"]);
+
+        let released = merge_platform_release(
+            &mut manifest,
+            "linux-x86_64",
+            detail("linux-1.1.0"),
+            "1.1.0",
+            "new notes",
+            "2024-02-01T00:00:00Z",
+            &required,
+        );
+        assert!(!released);
+        assert_eq!(manifest.version, "1.0.0");
+        assert_eq!(manifest.notes, "old notes");
+        // Clients keep getting the artifact of the released version until the bump
+        assert_eq!(manifest.platforms["linux-x86_64"].url, "linux-x86_64");
+        assert_eq!(manifest.pending["linux-x86_64"].url, "linux-1.1.0");
+        assert_eq!(
+            platform_entry(&manifest, "linux-x86_64", "1.1.0").map(|detail| &detail.url),
+            Some(&"linux-1.1.0".to_string())
+        );
+
+        let released = merge_platform_release(
+            &mut manifest,
+            "windows-x86_64",
+            detail("windows-1.1.0"),
+            "1.1.0",
+            "new notes",
+            "2024-02-01T00:00:00Z",
+            &required,
+        );
+        assert!(released);
+        assert_eq!(manifest.version, "1.1.0");
+        assert_eq!(manifest.notes, "new notes");
+        assert_eq!(manifest.pub_date, "2024-02-01T00:00:00Z");
+        assert_eq!(manifest.platforms["linux-x86_64"].url, "linux-1.1.0");
+        assert_eq!(manifest.platforms["windows-x86_64"].url, "windows-1.1.0");
+        assert!(manifest.pending.is_empty());
+    }
+
+    #[test]
+    fn platforms_publishing_after_the_bump_go_straight_in() {
+        let required = vec!["linux-x86_64".to_string()];
+        let mut manifest = manifest_at("1.0.0", &["linux-x86_64", "darwin-aarch64"]);
+        assert!(merge_platform_release(
+            &mut manifest,
+            "linux-x86_64",
+            detail("linux-1.1.0"),
+            "1.1.0",
+            "",
+            "",
+            &required,
+        ));
+        assert!(merge_platform_release(
+            &mut manifest,
+            "darwin-aarch64",
+            detail("darwin-1.1.0"),
+            "1.1.0",
+            "",
+            "",
+            &required,
+        ));
+        assert_eq!(manifest.platforms["darwin-aarch64"].url, "darwin-1.1.0");
+        assert!(manifest.pending.is_empty());
+    }
+
+    #[test]
+    fn promotion_keeps_entries_waiting_on_newer_versions() {
+        let required = vec!["linux-x86_64".to_string(), "windows-x86_64".to_string()];
+        let mut manifest = manifest_at("1.0.0", &["linux-x86_64", "windows-x86_64"]);
+        for (key, version) in [("darwin-aarch64", "1.2.0"), ("darwin-x86_64", "1.0.5")] {
+            let mut waiting = detail(key);
+            waiting.version = Some(version.to_string());
+            manifest.pending.insert(key.to_string(), waiting);
+        }
+
+        for key in ["linux-x86_64", "windows-x86_64"] {
+            merge_platform_release(
+                &mut manifest,
+                key,
+                detail(&format!("{}-1.1.0", key)),
+                "1.1.0",
+                "",
+                "",
+                &required,
+            );
+        }
+        assert_eq!(manifest.version, "1.1.0");
+        assert_eq!(
+            manifest.pending.keys().collect::<Vec<_>>(),
+            vec!["darwin-aarch64"]
+        );
+        assert!(!manifest.platforms.contains_key("darwin-x86_64"));
+    }
+
+    #[test]
+    fn merge_without_required_platforms_waits_for_every_listed_platform() {
+        let mut manifest = manifest_at("1.0.0", &["linux-x86_64", "darwin-aarch64"]);
+        assert!(!merge_platform_release(
+            &mut manifest,
+            "linux-x86_64",
+            detail("linux-1.1.0"),
+            "1.1.0",
+            "",
+            "",
+            &[],
+        ));
+
+        let mut manifest = GistContent::default();
+        assert!(merge_platform_release(
+            &mut manifest,
+            "linux-x86_64",
+            detail("linux-1.1.0"),
+            "1.1.0",
+            "",
+            "",
+            &[],
+        ));
+        assert_eq!(manifest.version, "1.1.0");
+    }
+
+    #[test]
+    fn policy_blocks_and_enforces_minimum() {
+        let policy = UpdatePolicy::new(&["v1.0.1".to_string(), " ".to_string()], "1.0.0");
+        assert_eq!(policy.blocked_versions, vec!["v1.0.1"]);
+        assert!(policy.is_blocked("1.0.1"));
+        assert!(policy.requires_update("1.0.1"));
+        assert!(policy.requires_update("0.9.9"));
+        assert!(!policy.requires_update("1.0.2"));
+        assert!(!UpdatePolicy::new(&[], "").requires_update("0.0.1"));
+    }
+}
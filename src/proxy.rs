@@ -0,0 +1,268 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::LocalSet;
+
+use crate::manifest::{GistContent, ManifestStore};
+use crate::redact::register_secret;
+use crate::serve::{read_request, write_response_head, HttpRequest};
+use crate::utilities::ProxyConfig;
+
+// Only requests to the Github API get the PAT, urls pointing anywhere else are fetched without it
+const AUTHENTICATED_HOST: &str = "https://api.github.com/";
+
+// How long a manifest read is served from memory. Every update check would otherwise be an
+// authenticated read against the manifest host, and Github rate limits those per PAT.
+const MANIFEST_CACHE_TTL: Duration = Duration::from_secs(30);
+
+struct Proxy {
+    store: Box<dyn ManifestStore>,
+    github_pat: String,
+    public_url: String,
+    license_tokens: Vec<String>,
+    cached_manifest: RefCell<Option<(Instant, Option<GistContent>)>>,
+}
+
+// Compares without stopping at the first differing byte, so response times don't give away how
+// much of a token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl Proxy {
+    // License token from the Authorization header or the token query parameter
+    fn license_token<'a>(&self, request: &'a HttpRequest) -> Option<&'a str> {
+        request
+            .header("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .or_else(|| request.query_param("token"))
+    }
+
+    fn authorized(&self, license_token: Option<&str>) -> bool {
+        self.license_tokens.is_empty()
+            || license_token.is_some_and(|token| {
+                self.license_tokens.iter().fold(false, |found, valid| {
+                    found | constant_time_eq(valid.as_bytes(), token.as_bytes())
+                })
+            })
+    }
+
+    // The published manifest, read from the store at most once per MANIFEST_CACHE_TTL
+    async fn current_manifest(&self) -> Result<Option<GistContent>, Box<dyn Error>> {
+        if let Some((read_at, manifest)) = self.cached_manifest.borrow().as_ref() {
+            if read_at.elapsed() < MANIFEST_CACHE_TTL {
+                return Ok(manifest.clone());
+            }
+        }
+        let manifest = self.store.read().await?.map(|(manifest, _)| manifest);
+        *self.cached_manifest.borrow_mut() = Some((Instant::now(), manifest.clone()));
+        Ok(manifest)
+    }
+
+    fn base_url(&self, request: &HttpRequest) -> String {
+        if self.public_url.is_empty() {
+            format!("http://{}", request.header("Host").unwrap_or("localhost"))
+        } else {
+            self.public_url.clone()
+        }
+    }
+
+    // The manifest with every platform url pointing back at this proxy
+    async fn manifest(&self, request: &HttpRequest) -> Result<String, Box<dyn Error>> {
+        let mut manifest = self
+            .current_manifest()
+            .await?
+            .ok_or("No manifest published yet")?;
+        let base_url = self.base_url(request);
+        // Tokens sent in the query are passed on, headers are sent with the download anyway
+        let query = match request.query_param("token") {
+            Some(token) if !self.license_tokens.is_empty() => format!("?token={}", token),
+            _ => String::new(),
+        };
+        // Pending entries hold upstream urls, and clients have no use for them
+        manifest.pending.clear();
+        let top_version = manifest.version.clone();
+        for (platform_key, detail) in manifest.platforms.iter_mut() {
+            let version = detail.version.as_deref().unwrap_or(&top_version);
+            detail.url = format!(
+                "{}/download/{}/{}{}",
+                base_url, platform_key, version, query
+            );
+        }
+        Ok(serde_json::to_string_pretty(&manifest)?)
+    }
+
+    // Upstream url of the artifact currently published for the platform at this version
+    async fn artifact_url(
+        &self,
+        platform_key: &str,
+        version: &str,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let Some(manifest) = self.current_manifest().await? else {
+            return Ok(None);
+        };
+        Ok(manifest.platforms.get(platform_key).and_then(|detail| {
+            let published = detail.version.as_deref().unwrap_or(&manifest.version);
+            (published == version).then(|| detail.url.clone())
+        }))
+    }
+
+    async fn stream_artifact(
+        &self,
+        stream: &mut TcpStream,
+        url: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut upstream = reqwest::Client::new()
+            .get(url)
+            .header("User-Agent", "javelin")
+            .header("Accept", "application/octet-stream");
+        if url.starts_with(AUTHENTICATED_HOST) {
+            upstream = upstream.bearer_auth(&self.github_pat);
+        }
+        let mut response = upstream.send().await?;
+
+        if !response.status().is_success() {
+            eprintln!("Upstream returned {} for an artifact", response.status());
+            write_response_head(stream, 502, "Bad Gateway", &[]).await?;
+            return Ok(());
+        }
+
+        let mut headers = vec![("Content-Type", "application/octet-stream".to_string())];
+        if let Some(length) = response.content_length() {
+            headers.push(("Content-Length", length.to_string()));
+        }
+        write_response_head(stream, 200, "OK", &headers).await?;
+        while let Some(chunk) = response.chunk().await? {
+            stream.write_all(&chunk).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle(&self, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+        let Some(request) = read_request(&mut stream).await? else {
+            return Ok(());
+        };
+        // Paths only, query strings may hold license tokens
+        let path = request.path().to_string();
+
+        let status = if request.method != "GET" {
+            write_response_head(&mut stream, 405, "Method Not Allowed", &[]).await?;
+            405
+        } else if !self.authorized(self.license_token(&request)) {
+            write_response_head(&mut stream, 401, "Unauthorized", &[]).await?;
+            401
+        } else {
+            let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+            match segments[..] {
+                ["latest.json"] => match self.manifest(&request).await {
+                    Ok(body) => {
+                        let headers = [
+                            ("Content-Type", "application/json".to_string()),
+                            ("Content-Length", body.len().to_string()),
+                            ("Cache-Control", "no-cache".to_string()),
+                        ];
+                        write_response_head(&mut stream, 200, "OK", &headers).await?;
+                        stream.write_all(body.as_bytes()).await?;
+                        200
+                    }
+                    Err(e) => {
+                        eprintln!("Error reading manifest: {}", e);
+                        write_response_head(&mut stream, 502, "Bad Gateway", &[]).await?;
+                        502
+                    }
+                },
+                ["download", platform_key, version] => {
+                    match self.artifact_url(platform_key, version).await {
+                        Ok(Some(url)) => {
+                            self.stream_artifact(&mut stream, &url).await?;
+                            200
+                        }
+                        Ok(None) => {
+                            write_response_head(&mut stream, 404, "Not Found", &[]).await?;
+                            404
+                        }
+                        Err(e) => {
+                            eprintln!("Error reading manifest: {}", e);
+                            write_response_head(&mut stream, 502, "Bad Gateway", &[]).await?;
+                            502
+                        }
+                    }
+                }
+                _ => {
+                    write_response_head(&mut stream, 404, "Not Found", &[]).await?;
+                    404
+                }
+            }
+        };
+
+        println!("{} {} -> {}", request.method, path, status);
+        stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+// Serves the manifest and release artifacts to clients using the PAT held here, so apps can
+// update from private repositories without shipping credentials
+pub async fn run_proxy(
+    address: &str,
+    store: Box<dyn ManifestStore>,
+    github_pat: &str,
+    config: &ProxyConfig,
+) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(address).await?;
+    for token in &config.license_tokens {
+        register_secret(token);
+    }
+    let proxy = Rc::new(Proxy {
+        store,
+        github_pat: github_pat.to_string(),
+        public_url: config.public_url.trim().trim_end_matches('/').to_string(),
+        license_tokens: config
+            .license_tokens
+            .iter()
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty())
+            .collect(),
+        cached_manifest: RefCell::new(None),
+    });
+
+    println!("Proxying updates on http://{}", address);
+    println!("Point the updater endpoint at {{proxy url}}/latest.json");
+    if !proxy.license_tokens.is_empty() {
+        println!("Clients must send a license token ('Authorization: Bearer' header or ?token=)");
+    }
+
+    // Manifest stores aren't Send, so connections are handled as local tasks on this thread
+    LocalSet::new()
+        .run_until(async move {
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => return Err(e.into()),
+                };
+                let proxy = Rc::clone(&proxy);
+                tokio::task::spawn_local(async move {
+                    if let Err(e) = proxy.handle(stream).await {
+                        eprintln!("Error handling request: {}", e);
+                    }
+                });
+            }
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_only_match_when_identical() {
+        assert!(constant_time_eq(b"license-1", b"license-1"));
+        assert!(!constant_time_eq(b"license-1", b"license-2"));
+        assert!(!constant_time_eq(b"license-1", b"license-10"));
+        assert!(!constant_time_eq(b"license-1", b""));
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+// Every secret value javelin has seen this run. Output goes through redact() before it's printed
+// or written, so none of them end up in terminal or CI logs.
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static WARNED_SHORT_SECRET: AtomicBool = AtomicBool::new(false);
+
+const MASK: &str = "********";
+// Shorter values would mask ordinary words and numbers all over the output, and in the journals
+// too, where masking parts of versions and keys would keep a run from being resumed
+const MIN_SECRET_LEN: usize = 4;
+
+pub fn register_secret(value: &str) {
+    let value = value.trim();
+    if value.is_empty() {
+        return;
+    }
+    if value.len() < MIN_SECRET_LEN {
+        if !WARNED_SHORT_SECRET.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "Warning: a secret shorter than {} characters can't be masked and may show up in the output. Use a longer password or token",
+                MIN_SECRET_LEN
+            );
+        }
+        return;
+    }
+    if let Ok(mut secrets) = SECRETS.lock() {
+        if !secrets.iter().any(|secret| secret == value) {
+            secrets.push(value.to_string());
+            // Longest first, so a secret containing another is masked whole
+            secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        }
+    }
+}
+
+// The text with every registered secret masked
+pub fn redact(text: &str) -> String {
+    let Ok(secrets) = SECRETS.lock() else {
+        return text.to_string();
+    };
+    secrets.iter().fold(text.to_string(), |text, secret| {
+        text.replace(secret.as_str(), MASK)
+    })
+}
+
+// Masks a secret for display regardless of its length, empty values stay visibly empty
+pub fn mask(value: &str) -> &'static str {
+    if value.trim().is_empty() {
+        ""
+    } else {
+        MASK
+    }
+}
+
+// Shadow the std printing macros for the whole crate (redact is declared first in main.rs), so
+// nothing reaches stdout or stderr without going through redact()
+macro_rules! println {
+    () => {
+        std::println!()
+    };
+    ($($arg:tt)*) => {
+        std::println!("{}", $crate::redact::redact(&format!($($arg)*)))
+    };
+}
+
+macro_rules! print {
+    ($($arg:tt)*) => {
+        std::print!("{}", $crate::redact::redact(&format!($($arg)*)))
+    };
+}
+
+macro_rules! eprintln {
+    () => {
+        std::eprintln!()
+    };
+    ($($arg:tt)*) => {
+        std::eprintln!("{}", $crate::redact::redact(&format!($($arg)*)))
+    };
+}
@@ -0,0 +1,389 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::error::JavelinError;
+use crate::gitea::GiteaReleaseHost;
+use crate::github::GithubReleaseHost;
+use crate::gitlab::GitlabReleaseHost;
+use crate::local::{LocalDir, LocalReleaseHost};
+use crate::run_lock::LockInfo;
+use crate::s3::{S3Client, S3ReleaseHost};
+use crate::utilities::{GiteaConfig, GitlabConfig, LocalConfig, S3Config};
+
+// Markers older than this are assumed to be left over from a run that died
+const RELEASE_MARKER_EXPIRY_MINUTES: i64 = 60;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HostRelease {
+    pub id: String,
+    pub tag: String,
+    // Github only, the templated url assets are uploaded to
+    #[serde(default)]
+    pub upload_url: String,
+    // Set when this run created the release rather than reusing an existing one
+    #[serde(default)]
+    pub created: bool,
+    // Set when creating the release also created its tag. A tag that was already there, e.g.
+    // pushed by the user, is left alone when the release is rolled back.
+    #[serde(default)]
+    pub tag_created: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HostAsset {
+    pub id: String,
+    pub name: String,
+    // Url the manifest points at, the API url where the host has one
+    pub url: String,
+    pub download_url: String,
+    // False while an upload is still in progress or was cut off
+    pub complete: bool,
+    pub label: Option<String>,
+    pub created_at: Option<String>,
+}
+
+// Somewhere releases and their downloadable assets live
+#[async_trait(?Send)]
+pub trait ReleaseHost {
+    fn name(&self) -> &'static str;
+
+    async fn find_or_create_release(
+        &self,
+        tag: &str,
+        notes: &str,
+    ) -> Result<HostRelease, Box<dyn Error>>;
+
+    async fn upload_asset(
+        &self,
+        release: &HostRelease,
+        path: &Path,
+        label: Option<&str>,
+    ) -> Result<HostAsset, Box<dyn Error>>;
+
+    // Uploads an asset only if the release has none of that name yet, returning None when another
+    // upload got there first. Github and GitLab refuse a second asset of the same name, so the
+    // plain upload is enough there.
+    async fn create_asset(
+        &self,
+        release: &HostRelease,
+        path: &Path,
+        label: Option<&str>,
+    ) -> Result<Option<HostAsset>, Box<dyn Error>> {
+        self.upload_asset(release, path, label).await.map(Some)
+    }
+
+    async fn list_assets(&self, release: &HostRelease) -> Result<Vec<HostAsset>, Box<dyn Error>>;
+
+    async fn download_asset(
+        &self,
+        release: &HostRelease,
+        asset: &HostAsset,
+    ) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    async fn delete_asset(
+        &self,
+        release: &HostRelease,
+        asset: &HostAsset,
+    ) -> Result<(), Box<dyn Error>>;
+
+    async fn delete_release(&self, release: &HostRelease) -> Result<(), Box<dyn Error>>;
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_release_host(
+    kind: &str,
+    github_user_repo: &str,
+    distribution_repo: &str,
+    github_pat: &str,
+    gitlab: &GitlabConfig,
+    gitea: &GiteaConfig,
+    s3: &S3Config,
+    local: &LocalConfig,
+) -> Result<Box<dyn ReleaseHost>, Box<dyn Error>> {
+    match kind {
+        "" | "github" if !distribution_repo.trim().is_empty() => Ok(Box::new(
+            GithubReleaseHost::distribution(distribution_repo.trim(), github_user_repo, github_pat),
+        )),
+        "" | "github" => Ok(Box::new(GithubReleaseHost::new(
+            github_user_repo,
+            github_pat,
+        ))),
+        "gitlab" => Ok(Box::new(GitlabReleaseHost::new(
+            &gitlab.url,
+            &gitlab.project,
+            &gitlab.token,
+        ))),
+        "gitea" | "forgejo" => {
+            if gitea.url.trim().is_empty() {
+                return Err(JavelinError::config(
+                    "gitea.url is not set",
+                    "Set gitea.url to the Gitea/Forgejo instance url, e.g. https://codeberg.org",
+                )
+                .into());
+            }
+            Ok(Box::new(GiteaReleaseHost::new(
+                &gitea.url,
+                &gitea.repo,
+                &gitea.token,
+            )))
+        }
+        "s3" => Ok(Box::new(S3ReleaseHost::new(S3Client::new(s3)?))),
+        "local" => Ok(Box::new(LocalReleaseHost::new(LocalDir::new(local)?))),
+        _ => Err(JavelinError::config(
+            format!("Unknown release_host '{}'", kind),
+            "Set release_host to 'github', 'gitlab', 'gitea', 'forgejo', 's3' or 'local'",
+        )
+        .into()),
+    }
+}
+
+// Percent encodes a single url path segment or query value, e.g. a "group/project" path or a tag
+pub fn encode_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn release_marker_name(platform_key: &str) -> String {
+    format!("javelin-in-progress-{}.lock", platform_key)
+}
+
+// Uploads a small "release in progress" asset so runs on other machines publishing the same
+// platform to this release back off. The marker file holds the run's LockInfo, which every host
+// can hand back, and the holder is put in the asset label too where the host has labels.
+// A marker is replaced if its asset id is in `own_markers` (recorded in the journal of a run that
+// crashed and is being resumed), if it was left by a run on this machine that is no longer
+// running, or once it's older than an hour.
+pub async fn acquire_release_marker(
+    host: &dyn ReleaseHost,
+    release: &HostRelease,
+    platform_key: &str,
+    holder: &LockInfo,
+    own_markers: &[String],
+) -> Result<HostAsset, Box<dyn Error>> {
+    let name = release_marker_name(platform_key);
+
+    let assets = host.list_assets(release).await?;
+    if let Some(existing) = assets.iter().find(|asset| asset.name == name) {
+        let existing_holder = host
+            .download_asset(release, existing)
+            .await
+            .ok()
+            .and_then(|content| serde_json::from_slice::<LockInfo>(&content).ok());
+        let expiry = Duration::minutes(RELEASE_MARKER_EXPIRY_MINUTES);
+        match &existing_holder {
+            _ if own_markers.contains(&existing.id) => {
+                println!("Replacing the release marker of this release's earlier run")
+            }
+            Some(info) if info.is_stale(expiry) => {
+                println!("Removing expired release marker held by {}", info.holder())
+            }
+            Some(info) => {
+                return Err(format!(
+                    "Release is already being published for {} by {} since {} (delete the {} asset if that run is gone)",
+                    platform_key, info.holder(), info.started_at, name
+                )
+                .into());
+            }
+            // Markers written by earlier versions only hold the holder's name, go by their age
+            None => {
+                let label = existing.label.as_deref().unwrap_or("another run");
+                let expired = existing
+                    .created_at
+                    .as_deref()
+                    .and_then(|created| DateTime::parse_from_rfc3339(created).ok())
+                    .is_some_and(|created| Utc::now() - created.with_timezone(&Utc) > expiry);
+                if !expired {
+                    return Err(format!(
+                        "Release is already being published for {} by {} (delete the {} asset if that run is gone)",
+                        platform_key, label, name
+                    )
+                    .into());
+                }
+                println!("Removing expired release marker held by {}", label);
+            }
+        }
+        host.delete_asset(release, existing).await?;
+    }
+
+    let marker_path = std::env::temp_dir().join(&name);
+    fs::write(&marker_path, serde_json::to_string_pretty(holder)?)?;
+    let marker = host
+        .create_asset(release, &marker_path, Some(&holder.holder()))
+        .await
+        // Most likely another run uploaded its marker between our check and upload
+        .map_err(|e| {
+            format!(
+                "Release is already being published for {}: {}",
+                platform_key, e
+            )
+        });
+    let _ = fs::remove_file(&marker_path);
+
+    marker?.ok_or_else(|| {
+        format!(
+            "Release is already being published for {}, another run uploaded its {} first",
+            platform_key, name
+        )
+        .into()
+    })
+}
+
+// Deletes assets with the given name that never finished uploading. Returns the number removed.
+pub async fn delete_incomplete_assets(
+    host: &dyn ReleaseHost,
+    release: &HostRelease,
+    name: &str,
+) -> Result<usize, Box<dyn Error>> {
+    let mut deleted = 0;
+    for asset in host.list_assets(release).await? {
+        if asset.name == name && !asset.complete {
+            host.delete_asset(release, &asset).await?;
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+// Which url of an uploaded asset the manifest points clients at
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetUrlStrategy {
+    // The host's API url, needs a token on private repos
+    Api,
+    // The public browser download url
+    Browser,
+    // A url template with {tag}, {name} and {platform} placeholders, e.g. a CDN in front of the host
+    Template(String),
+}
+
+impl AssetUrlStrategy {
+    // "api", "browser" or a template url. Empty picks the browser url for public distribution repos
+    // and the API url otherwise.
+    pub fn parse(value: &str, distribution_repo: &str) -> Result<Self, Box<dyn Error>> {
+        match value.trim() {
+            "" if distribution_repo.trim().is_empty() => Ok(AssetUrlStrategy::Api),
+            "" | "browser" => Ok(AssetUrlStrategy::Browser),
+            "api" => Ok(AssetUrlStrategy::Api),
+            template if template.starts_with("http://") || template.starts_with("https://") => {
+                if !template.contains("{name}") {
+                    return Err(JavelinError::config(
+                        format!("asset_url template '{}' has no {{name}}", template),
+                        "Add {name} where the asset file name goes, e.g. https://cdn.example.com/{tag}/{name}",
+                    )
+                    .into());
+                }
+                Ok(AssetUrlStrategy::Template(template.to_string()))
+            }
+            other => Err(JavelinError::config(
+                format!("Unknown asset_url '{}'", other),
+                "Set asset_url to 'api', 'browser' or a url template, or leave it empty",
+            )
+            .into()),
+        }
+    }
+
+    pub fn url_for(&self, asset: &HostAsset, tag: &str, platform_key: &str) -> String {
+        match self {
+            AssetUrlStrategy::Api => asset.url.clone(),
+            AssetUrlStrategy::Browser => asset.download_url.clone(),
+            AssetUrlStrategy::Template(template) => template
+                .replace("{tag}", tag)
+                .replace("{name}", &asset.name)
+                .replace("{platform}", platform_key),
+        }
+    }
+}
+
+const ASSET_URL_CHECK_ATTEMPTS: u64 = 3;
+
+// Checks the url serves the artifact before it goes into the manifest, retrying briefly in case a
+// CDN hasn't picked the upload up yet. Only the first byte is requested. The Github token is sent
+// to api.github.com only.
+pub async fn validate_asset_url(url: &str, github_pat: &str) -> Result<(), Box<dyn Error>> {
+    let client = reqwest::Client::new();
+    let mut last_status = None;
+    for attempt in 0..ASSET_URL_CHECK_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(std::time::Duration::from_secs(2 * attempt)).await;
+        }
+        let mut request = client
+            .get(url)
+            .header("User-Agent", "javelin")
+            .header("Accept", "application/octet-stream")
+            .header("Range", "bytes=0-0");
+        if url.starts_with("https://api.github.com/") {
+            request = request.bearer_auth(github_pat);
+        }
+        match request.send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => last_status = Some(response.status().to_string()),
+            Err(e) => last_status = Some(e.to_string()),
+        }
+    }
+    Err(JavelinError::config(
+        format!(
+            "Asset url {} doesn't resolve ({})",
+            url,
+            last_status.unwrap_or_default()
+        ),
+        "Check the asset_url setting, or pass --skip-url-check if the files are synced to the web server after the run",
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_segment_escapes_reserved_characters() {
+        assert_eq!(encode_segment("v1.0.0-beta_1~"), "v1.0.0-beta_1~");
+        assert_eq!(encode_segment("group/project"), "group%2Fproject");
+        assert_eq!(
+            encode_segment("My App 1.0+x&y=z?.tar.gz"),
+            "My%20App%201.0%2Bx%26y%3Dz%3F.tar.gz"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_second_release_marker_is_refused() {
+        let root = std::env::temp_dir().join(format!("javelin-marker-{}", std::process::id()));
+        let host = LocalReleaseHost::new(
+            LocalDir::new(&LocalConfig {
+                root: root.display().to_string(),
+                channel: "stable".to_string(),
+                base_url: "https://dl.example.com".to_string(),
+            })
+            .unwrap(),
+        );
+        let release = host.find_or_create_release("1.0.0", "").await.unwrap();
+        let holder = LockInfo::current();
+
+        let marker = acquire_release_marker(&host, &release, "linux-x86_64", &holder, &[])
+            .await
+            .unwrap();
+        // The create-if-absent upload itself refuses, not just the check before it
+        let marker_path = std::env::temp_dir().join(&marker.name);
+        fs::write(&marker_path, "{}").unwrap();
+        let second = host
+            .create_asset(&release, &marker_path, None)
+            .await
+            .unwrap();
+        let _ = fs::remove_file(&marker_path);
+        let again = acquire_release_marker(&host, &release, "linux-x86_64", &holder, &[]).await;
+        let _ = fs::remove_dir_all(&root);
+
+        assert!(second.is_none());
+        assert!(again.is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use std::error::Error;
+
+use crate::github::delete_gist;
+use crate::journal::{ReleaseJournal, RemoteObject};
+use crate::release_host::{delete_incomplete_assets, HostRelease, ReleaseHost};
+use crate::utilities::{patch_json_file, update_entry_in_config};
+
+// Undoes the remote objects recorded in the journal, newest first. Objects that fail to undo stay
+// in the journal so they can be cleaned up by hand (or by the next failed resume).
+// With `keep_on_failure` only the release in progress marker is removed.
+pub async fn rollback_remote_objects(
+    journal: &mut ReleaseJournal,
+    host: &dyn ReleaseHost,
+    token: &str,
+    config_path: &str,
+    keep_on_failure: bool,
+) {
+    if keep_on_failure {
+        println!("--keep-on-failure set, leaving remote changes in place");
+        release_markers(journal, host).await;
+        return;
+    }
+
+    if journal.created.is_empty() {
+        return;
+    }
+
+    println!("\nRolling back remote changes");
+
+    // Assets this run uploaded, anything else on a release it created belongs to another runner
+    let own_assets: Vec<String> = journal
+        .created
+        .iter()
+        .filter_map(|object| match object {
+            RemoteObject::Asset { name, .. } => Some(name.clone()),
+            RemoteObject::Marker { asset, .. } => Some(asset.name.clone()),
+            _ => None,
+        })
+        .collect();
+
+    while let Some(object) = journal.created.pop() {
+        let result = match &object {
+            RemoteObject::Marker { release, asset } => host.delete_asset(release, asset).await,
+            RemoteObject::Asset {
+                release,
+                asset: Some(asset),
+                name,
+            } => host.delete_asset(release, asset).await.map(|_| {
+                println!("Deleted asset {}", name);
+                journal.asset_url = None;
+            }),
+            RemoteObject::Asset {
+                release,
+                name,
+                asset: None,
+            } => delete_incomplete_assets(host, release, name)
+                .await
+                .map(|deleted| println!("Deleted {} incomplete asset(s) named {}", deleted, name)),
+            RemoteObject::Release { release } => {
+                delete_release_if_unused(host, release, &own_assets)
+                    .await
+                    .map(|deleted| {
+                        if deleted {
+                            println!("Deleted release {} ({})", release.tag, release.id);
+                        } else {
+                            println!("Keeping release {}, other runs uploaded to it", release.tag);
+                        }
+                        journal.release = None;
+                        journal.asset_url = None;
+                    })
+            }
+            RemoteObject::Gist {
+                id,
+                key_path,
+                tauri_config_path,
+                endpoints,
+            } => delete_gist(id, token).await.and_then(|_| {
+                println!("Deleted gist {}", id);
+                // Journals from before the key path was recorded only knew the top level key
+                let key_path: Vec<&str> = if key_path.is_empty() {
+                    vec!["gist_id"]
+                } else {
+                    key_path.iter().map(String::as_str).collect()
+                };
+                update_entry_in_config(config_path, &key_path, "")?;
+                if let Some(endpoints) = endpoints {
+                    patch_json_file(
+                        tauri_config_path,
+                        &[(&["tauri", "updater", "endpoints"], endpoints.clone().into())],
+                    )?;
+                    println!("Restored the updater endpoints in {}", tauri_config_path);
+                }
+                Ok(())
+            }),
+        };
+
+        if let Err(e) = result {
+            eprintln!("Failed to roll back {:?}: {}", object, e);
+            journal.created.push(object);
+            break;
+        }
+    }
+
+    if let Err(e) = journal.save() {
+        eprintln!("Failed to save release journal: {}", e);
+    }
+}
+
+// Deletes a release this run created unless it holds assets this run didn't upload. Runners for
+// other platforms share the release tag, so their assets may already be live in the manifest.
+// Returns whether the release was deleted.
+async fn delete_release_if_unused(
+    host: &dyn ReleaseHost,
+    release: &HostRelease,
+    own_assets: &[String],
+) -> Result<bool, Box<dyn Error>> {
+    let assets = host.list_assets(release).await?;
+    if assets.iter().any(|asset| !own_assets.contains(&asset.name)) {
+        return Ok(false);
+    }
+    // Deleting the release removes any of this run's assets still left on it
+    host.delete_release(release).await?;
+    Ok(true)
+}
+
+// Removes any release in progress markers, leaving every other remote object alone
+pub async fn release_markers(journal: &mut ReleaseJournal, host: &dyn ReleaseHost) {
+    let mut remaining = Vec::new();
+    for object in journal.created.drain(..) {
+        if let RemoteObject::Marker { release, asset } = &object {
+            if let Err(e) = host.delete_asset(release, asset).await {
+                eprintln!("Failed to remove release marker {}: {}", asset.name, e);
+                remaining.push(object);
+            }
+        } else {
+            remaining.push(object);
+        }
+    }
+    journal.created = remaining;
+
+    if let Err(e) = journal.save() {
+        eprintln!("Failed to save release journal: {}", e);
+    }
+}
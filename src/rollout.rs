@@ -0,0 +1,206 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+
+use crate::journal::list_journals;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RolloutState {
+    Active,
+    // Not offered to anyone until resumed
+    Paused,
+    // Withdrawn for good, clients are offered the previous release instead
+    Halted,
+}
+
+// Percentage of clients a version is offered to, moved through its stages by hand
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Rollout {
+    // Ascending percentages, always ending at 100
+    pub stages: Vec<u8>,
+    pub stage: usize,
+    pub state: RolloutState,
+    pub updated_at: String,
+}
+
+impl Rollout {
+    pub fn new(stages: &[u8]) -> Self {
+        let mut stages: Vec<u8> = stages.iter().map(|stage| (*stage).clamp(1, 100)).collect();
+        stages.sort_unstable();
+        stages.dedup();
+        if stages.last() != Some(&100) {
+            stages.push(100);
+        }
+        Rollout {
+            stages,
+            stage: 0,
+            state: RolloutState::Active,
+            updated_at: Utc::now().to_rfc3339(),
+        }
+    }
+
+    pub fn percentage(&self) -> u8 {
+        match self.state {
+            RolloutState::Active => self.stages.get(self.stage).copied().unwrap_or(100),
+            RolloutState::Paused | RolloutState::Halted => 0,
+        }
+    }
+
+    // Whether the client with this id falls into the offered fraction of this version. Buckets are
+    // stable for a version, so clients offered the update at one stage stay offered at the next.
+    pub fn includes(&self, version: &str, client_id: &str) -> bool {
+        let hash = Sha256::digest(format!("{}:{}", version, client_id).as_bytes());
+        let bucket = u64::from_be_bytes(hash[..8].try_into().unwrap_or_default()) % 100;
+        bucket < self.percentage() as u64
+    }
+
+    pub fn describe(&self) -> String {
+        let stage = format!(
+            "stage {}/{} ({}%)",
+            self.stage + 1,
+            self.stages.len(),
+            self.stages.get(self.stage).copied().unwrap_or(100)
+        );
+        match self.state {
+            RolloutState::Active => stage,
+            RolloutState::Paused => format!("paused at {}", stage),
+            RolloutState::Halted => format!("halted at {}", stage),
+        }
+    }
+
+    fn apply(&mut self, action: &str) -> Result<(), Box<dyn Error>> {
+        match (action, self.state) {
+            (_, RolloutState::Halted) => {
+                return Err("Rollout was halted, publish a new version instead".into())
+            }
+            ("advance", RolloutState::Paused) => {
+                return Err("Rollout is paused, resume it before advancing".into())
+            }
+            ("advance", RolloutState::Active) => {
+                if self.stage + 1 >= self.stages.len() {
+                    return Err("Rollout is already at 100%".into());
+                }
+                self.stage += 1;
+            }
+            ("pause", _) => self.state = RolloutState::Paused,
+            ("resume", _) => self.state = RolloutState::Active,
+            ("halt", _) => self.state = RolloutState::Halted,
+            _ => {
+                return Err(format!(
+                    "Unknown rollout action '{}', expected advance, pause, resume or halt",
+                    action
+                )
+                .into())
+            }
+        }
+        self.updated_at = Utc::now().to_rfc3339();
+        Ok(())
+    }
+}
+
+// Applies an action to the rollout of a version on every platform recorded here for the profile,
+// or just prints the rollouts for "status"
+pub fn update_rollout(
+    action: &str,
+    version: &str,
+    profile: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let mut journals: Vec<_> = list_journals(profile)?
+        .into_iter()
+        .filter(|journal| journal.new_version == version)
+        .collect();
+    if journals.is_empty() {
+        return Err(format!("No release recorded for version {}", version).into());
+    }
+
+    for journal in journals.iter_mut() {
+        let Some(rollout) = journal.rollout.as_mut() else {
+            println!("{} {} : not staged", version, journal.platform_key);
+            continue;
+        };
+        if action != "status" {
+            rollout.apply(action)?;
+        }
+        println!(
+            "{} {} : {}",
+            version,
+            journal.platform_key,
+            rollout.describe()
+        );
+    }
+
+    if action != "status" {
+        for journal in &journals {
+            journal.save()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offered(rollout: &Rollout, version: &str) -> usize {
+        (0..1000)
+            .filter(|client| rollout.includes(version, &format!("client-{}", client)))
+            .count()
+    }
+
+    #[test]
+    fn new_normalizes_stages() {
+        assert_eq!(Rollout::new(&[50, 0, 10, 50]).stages, vec![1, 10, 50, 100]);
+        assert_eq!(Rollout::new(&[]).stages, vec![100]);
+    }
+
+    #[test]
+    fn buckets_match_the_percentage() {
+        let mut rollout = Rollout::new(&[10, 50]);
+        let at_ten = offered(&rollout, "1.0.0");
+        assert!((60..=140).contains(&at_ten), "{} of 1000 offered", at_ten);
+
+        rollout.apply("advance").unwrap();
+        let at_fifty = offered(&rollout, "1.0.0");
+        assert!(
+            (420..=580).contains(&at_fifty),
+            "{} of 1000 offered",
+            at_fifty
+        );
+
+        rollout.apply("advance").unwrap();
+        assert_eq!(offered(&rollout, "1.0.0"), 1000);
+    }
+
+    #[test]
+    fn buckets_are_stable_across_stages() {
+        let mut rollout = Rollout::new(&[10, 50]);
+        let early: Vec<String> = (0..1000)
+            .map(|client| format!("client-{}", client))
+            .filter(|client| rollout.includes("1.0.0", client))
+            .collect();
+        rollout.apply("advance").unwrap();
+        assert!(early.iter().all(|client| rollout.includes("1.0.0", client)));
+    }
+
+    #[test]
+    fn paused_and_halted_offer_nobody() {
+        let mut rollout = Rollout::new(&[50]);
+        rollout.apply("pause").unwrap();
+        assert_eq!(offered(&rollout, "1.0.0"), 0);
+        assert!(rollout.apply("advance").is_err());
+
+        rollout.apply("resume").unwrap();
+        rollout.apply("halt").unwrap();
+        assert_eq!(offered(&rollout, "1.0.0"), 0);
+        assert!(rollout.apply("resume").is_err());
+    }
+
+    #[test]
+    fn advance_stops_at_full_rollout() {
+        let mut rollout = Rollout::new(&[]);
+        assert!(rollout.apply("advance").is_err());
+        assert!(rollout.apply("skip").is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::error::JavelinError;
+
+// Locks from other machines can't be checked for a live process, so they expire after this
+const REMOTE_LOCK_EXPIRY_HOURS: i64 = 12;
+
+static LOCK_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+// Removes the lock file when dropped. `std::process::exit` skips destructors, so exit paths call
+// `release_run_lock` themselves.
+pub struct RunLock;
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        release_run_lock();
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LockInfo {
+    pub pid: u32,
+    pub host: String,
+    pub started_at: String,
+}
+
+impl LockInfo {
+    pub fn current() -> Self {
+        LockInfo {
+            pid: std::process::id(),
+            host: hostname(),
+            started_at: Utc::now().to_rfc3339(),
+        }
+    }
+
+    pub fn holder(&self) -> String {
+        format!("{}@{}", self.pid, self.host)
+    }
+
+    // Whether the run that wrote this is gone: on this machine if its process isn't running,
+    // elsewhere once it's older than `remote_expiry`
+    pub fn is_stale(&self, remote_expiry: Duration) -> bool {
+        if self.host == hostname() {
+            return !process_alive(self.pid);
+        }
+        DateTime::parse_from_rfc3339(&self.started_at).map_or(true, |started| {
+            Utc::now() - started.with_timezone(&Utc) > remote_expiry
+        })
+    }
+}
+
+pub fn hostname() -> String {
+    let from_env = std::env::var("HOSTNAME").or_else(|_| std::env::var("COMPUTERNAME"));
+    if let Ok(host) = from_env {
+        if !host.trim().is_empty() {
+            return host.trim().to_string();
+        }
+    }
+    Command::new("hostname")
+        .output()
+        .ok()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "unknown-host".to_string())
+}
+
+fn process_alive(pid: u32) -> bool {
+    let status = if cfg!(target_os = "windows") {
+        Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+    } else {
+        Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .output()
+            .map(|output| output.status.success())
+    };
+    // If we can't tell, assume the other run is still going
+    status.unwrap_or(true)
+}
+
+// Takes the project lock for the duration of the run. A lock left behind by a process that is no
+// longer running (or an expired one from another machine) is replaced.
+pub fn acquire_run_lock(base_dir: &str) -> Result<RunLock, Box<dyn Error>> {
+    let path = Path::new(base_dir).join(".javelin.lock");
+    let info = LockInfo::current();
+
+    // The lock is written in full to a file of our own and hard linked into place, so another run
+    // never sees a lock file that exists but is still empty
+    let temp_path = Path::new(base_dir).join(format!(".javelin.lock.{}", info.pid));
+    fs::write(&temp_path, serde_json::to_string_pretty(&info)?)?;
+    let result = link_run_lock(&path, &temp_path);
+    let _ = fs::remove_file(&temp_path);
+    result?;
+
+    let _ = LOCK_PATH.set(path);
+    Ok(RunLock)
+}
+
+fn link_run_lock(path: &Path, temp_path: &Path) -> Result<(), Box<dyn Error>> {
+    for _ in 0..2 {
+        match fs::hard_link(temp_path, path) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                let content = fs::read_to_string(path).unwrap_or_default();
+                match serde_json::from_str::<LockInfo>(&content).ok() {
+                    Some(existing)
+                        if !existing.is_stale(Duration::hours(REMOTE_LOCK_EXPIRY_HOURS)) =>
+                    {
+                        return Err(JavelinError::config(
+                            format!(
+                                "Another javelin run ({}) has held {} since {}",
+                                existing.holder(),
+                                path.display(),
+                                existing.started_at
+                            ),
+                            "Wait for the other run to finish, or delete the lock file if that run is gone",
+                        )
+                        .into());
+                    }
+                    Some(existing) => {
+                        println!("Removing stale lock held by {}", existing.holder());
+                        remove_stale_lock(path, temp_path, &content)?;
+                    }
+                    None => {
+                        println!("Removing unreadable lock file {}", path.display());
+                        remove_stale_lock(path, temp_path, &content)?;
+                    }
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Err(JavelinError::config(
+        format!("Failed to take lock {}", path.display()),
+        "Another run keeps replacing the lock, wait for it to finish and try again",
+    )
+    .into())
+}
+
+// Moves the stale lock aside rather than deleting it, so that if another run replaced it in the
+// meantime its fresh lock can be put back instead of being removed
+fn remove_stale_lock(path: &Path, temp_path: &Path, stale: &str) -> Result<(), Box<dyn Error>> {
+    let aside = PathBuf::from(format!("{}.stale", temp_path.display()));
+    match fs::rename(path, &aside) {
+        Ok(()) => {}
+        // Another run got to it first
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+    let moved = fs::read_to_string(&aside).unwrap_or_default();
+    if moved != stale {
+        let restored = fs::hard_link(&aside, path);
+        let _ = fs::remove_file(&aside);
+        restored?;
+        return Err(JavelinError::config(
+            format!(
+                "Another javelin run took {} while the stale lock was being removed",
+                path.display()
+            ),
+            "Wait for the other run to finish and try again",
+        )
+        .into());
+    }
+    fs::remove_file(&aside)?;
+    Ok(())
+}
+
+pub fn release_run_lock() {
+    if let Some(path) = LOCK_PATH.get() {
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,336 @@
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{global_config_path, javelin_config_dir};
+use crate::error::JavelinError;
+use crate::redact::register_secret;
+use crate::utilities::{read_value, Config};
+
+// Config entries holding credentials. They can be given as references, which are resolved when
+// javelin runs and never written back to the config:
+//   env:GITHUB_TOKEN            an environment variable
+//   file:~/.secrets/github-pat  the contents of a file
+//   cmd:pass show github/pat    the output of a command
+pub const SECRET_KEYS: &[&[&str]] = &[
+    &["github_pat"],
+    &["secret_key_password"],
+    &["gitlab", "token"],
+    &["gitea", "token"],
+    &["s3", "access_key"],
+    &["s3", "secret_key"],
+];
+
+// Whether a dotted key path names a secret, at the top level or nested, e.g. under a profile
+pub fn is_secret_key(key: &str) -> bool {
+    SECRET_KEYS.iter().any(|key_path| {
+        let path = key_path.join(".");
+        key == path || key.ends_with(&format!(".{}", path))
+    })
+}
+
+pub fn is_reference(value: &str) -> bool {
+    ["env:", "file:", "cmd:"]
+        .iter()
+        .any(|prefix| value.trim().starts_with(prefix))
+}
+
+// The value a reference points at, plain values are returned unchanged
+pub fn resolve_secret(value: &str) -> Result<String, Box<dyn Error>> {
+    let value = value.trim();
+    if let Some(name) = value.strip_prefix("env:") {
+        env::var(name.trim()).map_err(|_| {
+            JavelinError::config(
+                format!("Environment variable {} is not set", name.trim()),
+                "Export the variable, or point the setting at a file: or cmd: reference",
+            )
+            .into()
+        })
+    } else if let Some(path) = value.strip_prefix("file:") {
+        let path = shellexpand::tilde(path.trim()).into_owned();
+        fs::read_to_string(&path)
+            .map(|contents| contents.trim().to_string())
+            .map_err(|e| {
+                JavelinError::config(
+                    format!("Couldn't read secret file {}: {}", path, e),
+                    "Check the file: path, 'javelin migrate-secrets' writes these files",
+                )
+                .into()
+            })
+    } else if let Some(command) = value.strip_prefix("cmd:") {
+        let output = if cfg!(target_os = "windows") {
+            Command::new("cmd").args(["/C", command.trim()]).output()?
+        } else {
+            Command::new("sh").args(["-c", command.trim()]).output()?
+        };
+        if !output.status.success() {
+            return Err(JavelinError::config(
+                format!(
+                    "Secret command '{}' failed: {}",
+                    command.trim(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                "Run the cmd: command in a shell to see why it fails, e.g. a locked password manager",
+            )
+            .into());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+// Replaces every secret reference in the config with the value it points at
+pub fn resolve_secrets(config: &mut Config) -> Result<(), Box<dyn Error>> {
+    for (key, value) in [
+        ("github_pat", &mut config.github_pat),
+        ("secret_key_password", &mut config.secret_key_password),
+        ("gitlab.token", &mut config.gitlab.token),
+        ("gitea.token", &mut config.gitea.token),
+        ("s3.access_key", &mut config.s3.access_key),
+        ("s3.secret_key", &mut config.s3.secret_key),
+    ] {
+        // Name the setting while keeping the hint of the reference that failed
+        *value = resolve_secret(value).map_err(|e| match e.downcast_ref::<JavelinError>() {
+            Some(JavelinError::Config { message, hint }) => {
+                JavelinError::config(format!("{}: {}", key, message), hint)
+            }
+            _ => JavelinError::config(
+                format!("{}: {}", key, e),
+                "Check the setting's env:, file: or cmd: reference",
+            ),
+        })?;
+        register_secret(value);
+    }
+    Ok(())
+}
+
+fn entry<'a>(config: &'a Value, key_path: &[String]) -> Option<&'a str> {
+    key_path
+        .iter()
+        .try_fold(config, |value, key| value.get(key))
+        .and_then(Value::as_str)
+}
+
+// Every place a secret can be set: the SECRET_KEYS at the top level and under each profile
+fn secret_key_paths(config: &Value) -> Vec<Vec<String>> {
+    let profiles = config
+        .get("profiles")
+        .and_then(Value::as_object)
+        .map(|profiles| profiles.keys().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    let scopes = std::iter::once(Vec::new()).chain(
+        profiles
+            .into_iter()
+            .map(|profile| vec!["profiles".to_string(), profile]),
+    );
+    scopes
+        .flat_map(|scope| {
+            SECRET_KEYS.iter().map(move |key_path| {
+                scope
+                    .iter()
+                    .cloned()
+                    .chain(key_path.iter().map(|key| key.to_string()))
+                    .collect()
+            })
+        })
+        .collect()
+}
+
+// Secret entries the config holds in plaintext, as dotted key paths
+pub fn plaintext_secrets(config: &Value) -> Vec<String> {
+    secret_key_paths(config)
+        .into_iter()
+        .filter(|key_path| {
+            entry(config, key_path)
+                .is_some_and(|value| !value.trim().is_empty() && !is_reference(value))
+        })
+        .map(|key_path| key_path.join("."))
+        .collect()
+}
+
+// ~/.config/javelin/secrets/<project directory>-<hash>. The hash is of the full project path, so
+// two checkouts with the same directory name don't share (and overwrite) each other's secrets.
+fn secrets_dir() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(javelin_config_dir()
+        .ok_or("No home directory on this system")?
+        .join("secrets")
+        .join(project_dir_name(&env::current_dir()?.canonicalize()?)))
+}
+
+fn project_dir_name(project: &Path) -> String {
+    let name = project
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "default".to_string());
+    let hash = Sha256::digest(project.to_string_lossy().as_bytes());
+    format!("{}-{}", name, &hex::encode(hash)[..16])
+}
+
+// ~/.config/javelin/global-secrets, for secrets of the global config.toml shared by every project
+fn global_secrets_dir() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(javelin_config_dir()
+        .ok_or("No home directory on this system")?
+        .join("global-secrets"))
+}
+
+// Creates the file readable only by the current user, so the secret is never on disk with wider
+// permissions. An existing file is narrowed before it is overwritten.
+fn write_private(path: &Path, contents: &str) -> Result<(), Box<dyn Error>> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        let file = options.open(path)?;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        (&file).write_all(contents.as_bytes())?;
+    }
+    #[cfg(not(unix))]
+    options.open(path)?.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+// Writes a secret to a file readable only by the current user, returning the file: reference to
+// put in the config instead. `key` is the dotted key path the secret belongs to.
+pub fn store_secret(key: &str, value: &str) -> Result<String, Box<dyn Error>> {
+    store_secret_in(&secrets_dir()?, key, value)
+}
+
+fn store_secret_in(dir: &Path, key: &str, value: &str) -> Result<String, Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(key);
+    write_private(&path, value.trim())?;
+    Ok(format!("file:{}", path.display()))
+}
+
+// Replaces the plaintext secrets in a parsed config with file: references to files in `dir`,
+// returning the migrated key paths
+fn migrate_value(config: &mut Value, dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut migrated = Vec::new();
+    for key_path in secret_key_paths(config) {
+        let Some(value) = entry(config, &key_path) else {
+            continue;
+        };
+        if value.trim().is_empty() || is_reference(value) {
+            continue;
+        }
+        let key = key_path.join(".");
+        let reference = Value::String(store_secret_in(dir, &key, value)?);
+        if let Some(slot) = key_path
+            .iter()
+            .try_fold(&mut *config, |value, key| value.get_mut(key))
+        {
+            *slot = reference;
+        }
+        migrated.push(key);
+    }
+    Ok(migrated)
+}
+
+// Moves plaintext secrets out of the config, profiles included, into files readable only by the
+// current user and replaces them with file: references. Returns the migrated key paths.
+pub fn migrate_secrets(config_path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut config: Value = serde_json::from_str(&fs::read_to_string(config_path)?)?;
+
+    let migrated = migrate_value(&mut config, &secrets_dir()?)?;
+    if !migrated.is_empty() {
+        fs::write(config_path, serde_json::to_string_pretty(&config)?)?;
+    }
+    Ok(migrated)
+}
+
+// Same for the global config.toml, if there is one. The file is written back from the parsed
+// values, so comments in it are lost when anything is migrated.
+pub fn migrate_global_secrets() -> Result<Vec<String>, Box<dyn Error>> {
+    let Some(path) = global_config_path().filter(|path| path.exists()) else {
+        return Ok(Vec::new());
+    };
+    let mut config: Value = toml::from_str(&fs::read_to_string(&path)?)?;
+
+    let migrated = migrate_value(&mut config, &global_secrets_dir()?)?;
+    if !migrated.is_empty() {
+        // toml::Value writes plain values before tables, as TOML requires
+        fs::write(
+            &path,
+            toml::to_string_pretty(&toml::Value::try_from(&config)?)?,
+        )?;
+    }
+    Ok(migrated)
+}
+
+// Prompts for a missing secret like read_value, but the answer is only used for this run
+pub fn read_secret(prompt: &str, key: &str, value: &mut String) -> Result<(), Box<dyn Error>> {
+    if value.trim().is_empty() {
+        read_value(prompt, value)?;
+        register_secret(value);
+        println!(
+            "{} is not saved, set {} to an env:, file: or cmd: reference to keep it",
+            prompt, key
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn secret_keys_match_nested_paths() {
+        assert!(is_secret_key("github_pat"));
+        assert!(is_secret_key("profiles.ci.github_pat"));
+        assert!(is_secret_key("profiles.ci.s3.secret_key"));
+        assert!(!is_secret_key("s3.bucket"));
+        assert!(!is_secret_key("profiles.ci.token"));
+        assert!(!is_secret_key("not_github_pat"));
+    }
+
+    #[test]
+    fn finds_plaintext_secrets_in_profiles() {
+        let config = json!({
+            "github_pat": "env:GITHUB_TOKEN",
+            "s3": {"secret_key": "plain"},
+            "profiles": {
+                "ci": {"github_pat": "ghp_plain", "gitea": {"token": "file:~/token"}},
+                "staging": {"s3": {"access_key": ""}}
+            }
+        });
+        assert_eq!(
+            plaintext_secrets(&config),
+            vec!["s3.secret_key", "profiles.ci.github_pat"]
+        );
+    }
+
+    #[test]
+    fn projects_with_the_same_directory_name_get_their_own_secrets() {
+        let first = project_dir_name(Path::new("/work/client-a/app"));
+        let second = project_dir_name(Path::new("/work/client-b/app"));
+        assert!(first.starts_with("app-"));
+        assert_ne!(first, second);
+        assert_eq!(first, project_dir_name(Path::new("/work/client-a/app")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn secret_files_are_only_readable_by_the_owner() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("javelin-secrets-{}", std::process::id()));
+        let reference = store_secret_in(&dir, "github_pat", " ghp_value\n").unwrap();
+        let path = dir.join("github_pat");
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        let contents = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(mode, 0o600);
+        assert_eq!(contents, "ghp_value");
+        assert_eq!(reference, format!("file:{}", path.display()));
+    }
+}
@@ -0,0 +1,356 @@
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::cli::CliArgs;
+use crate::config::{LayeredConfig, PROJECT_CONFIG};
+use crate::journal::{list_journals, ReleaseJournal};
+use crate::manifest::{version_key, GistContent, UpdatePolicy};
+use crate::rollout::{Rollout, RolloutState};
+
+// Requests larger than this are rejected, the updater only ever sends a short GET
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+// Header clients can send to be bucketed by installation rather than by IP address
+const INSTALLATION_ID_HEADER: &str = "x-installation-id";
+
+// The platform specific response format of the Tauri dynamic updater
+#[derive(Debug, Serialize, Clone)]
+pub struct ServedRelease {
+    pub version: String,
+    pub notes: String,
+    // Left out rather than sent empty, the updater fails to parse an empty date
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pub_date: Option<String>,
+    pub url: String,
+    pub signature: String,
+    // Set when the client is on a blocked or unsupported version and has to update
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub critical: bool,
+    #[serde(skip)]
+    pub rollout: Option<Rollout>,
+}
+
+// Where 'javelin serve' looks up releases. Read again on every request so new releases are served
+// without a restart.
+#[derive(Debug, Clone)]
+pub enum ReleaseSource {
+    // Completed release journals in .javelin/journal
+    Journals,
+    // A latest.json manifest in a publish directory, e.g. <root>/<channel> of the local publisher
+    PublishDir(PathBuf),
+}
+
+impl ReleaseSource {
+    pub fn describe(&self) -> String {
+        match self {
+            ReleaseSource::Journals => "release journals".to_string(),
+            ReleaseSource::PublishDir(dir) => dir.join("latest.json").display().to_string(),
+        }
+    }
+
+    // Releases published for the platform under the profile, newest first, and the update policy
+    // they're served under
+    pub fn releases(
+        &self,
+        platform_key: &str,
+        profile: Option<&str>,
+    ) -> Result<(Vec<ServedRelease>, UpdatePolicy), Box<dyn Error>> {
+        let journals: Vec<ReleaseJournal> = list_journals(profile)?
+            .into_iter()
+            .filter(|journal| journal.completed && journal.platform_key == platform_key)
+            .collect();
+
+        let (mut releases, policy): (Vec<ServedRelease>, UpdatePolicy) = match self {
+            ReleaseSource::Journals => {
+                // The policy is read from the config on every request, so changes apply immediately
+                let config =
+                    LayeredConfig::load(PROJECT_CONFIG, profile, &CliArgs::default())?.config()?;
+                let policy = UpdatePolicy::new(&config.blocked_versions, &config.minimum_version);
+                let releases = journals
+                    .into_iter()
+                    .map(|journal| ServedRelease {
+                        version: journal.new_version,
+                        notes: journal.notes,
+                        pub_date: journal.published_at,
+                        url: journal.asset_url.unwrap_or_default(),
+                        signature: journal.signature.unwrap_or_default(),
+                        critical: false,
+                        rollout: journal.rollout,
+                    })
+                    .collect();
+                (releases, policy)
+            }
+            ReleaseSource::PublishDir(dir) => {
+                let manifest: GistContent =
+                    serde_json::from_str(&fs::read_to_string(dir.join("latest.json"))?)?;
+                let releases = manifest
+                    .platforms
+                    .get(platform_key)
+                    .map(|detail| {
+                        let version = detail
+                            .version
+                            .clone()
+                            .unwrap_or_else(|| manifest.version.clone());
+                        // Notes and date belong to the top level version
+                        let (notes, pub_date) = if version == manifest.version {
+                            (
+                                manifest.notes.clone(),
+                                Some(manifest.pub_date.clone()).filter(|date| !date.is_empty()),
+                            )
+                        } else {
+                            (String::new(), None)
+                        };
+                        // Rollouts are recorded in the journals of the project serving the directory
+                        let rollout = journals
+                            .iter()
+                            .find(|journal| journal.new_version == version)
+                            .and_then(|journal| journal.rollout.clone());
+                        ServedRelease {
+                            version,
+                            notes,
+                            pub_date,
+                            url: detail.url.clone(),
+                            signature: detail.signature.clone(),
+                            critical: false,
+                            rollout,
+                        }
+                    })
+                    .into_iter()
+                    .collect();
+                (releases, manifest.policy)
+            }
+        };
+
+        releases.sort_by_key(|release| std::cmp::Reverse(version_key(&release.version)));
+        Ok((releases, policy))
+    }
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    body: Option<String>,
+}
+
+impl Response {
+    fn empty(status: u16, reason: &'static str) -> Self {
+        Response {
+            status,
+            reason,
+            body: None,
+        }
+    }
+
+    fn json(body: String) -> Self {
+        Response {
+            status: 200,
+            reason: "OK",
+            body: Some(body),
+        }
+    }
+}
+
+// Answers GET /{target}/{arch}/{current_version}: 204 when the client is up to date, otherwise
+// the newest release for its platform that is being rolled out to it. Clients on a blocked or
+// unsupported version skip the rollout and get the newest release, flagged critical.
+fn respond(server: &Server, method: &str, path: &str, client_id: &str) -> Response {
+    if method != "GET" {
+        return Response::empty(405, "Method Not Allowed");
+    }
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let [target, arch, current_version] = segments[..] else {
+        return Response::empty(404, "Not Found");
+    };
+    let platform_key = format!("{}-{}", target, arch);
+
+    let (releases, policy) = match server
+        .source
+        .releases(&platform_key, server.profile.as_deref())
+    {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("Error reading releases for {}: {}", platform_key, e);
+            return Response::empty(500, "Internal Server Error");
+        }
+    };
+
+    let current = version_key(current_version);
+    let forced = policy.requires_update(current_version);
+    let Some(release) = releases
+        .iter()
+        .take_while(|release| version_key(&release.version) > current)
+        .filter(|release| !policy.is_blocked(&release.version))
+        .find(|release| match &release.rollout {
+            None => true,
+            Some(rollout) if forced => rollout.state != RolloutState::Halted,
+            Some(rollout) => rollout.includes(&release.version, client_id),
+        })
+    else {
+        return Response::empty(204, "No Content");
+    };
+
+    let mut release = release.clone();
+    if forced {
+        release.critical = true;
+        release.notes = format!(
+            "Critical update: version {} is no longer supported. {}",
+            current_version, release.notes
+        );
+    }
+
+    match serde_json::to_string(&release) {
+        Ok(body) => Response::json(body),
+        Err(_) => Response::empty(500, "Internal Server Error"),
+    }
+}
+
+// Request line and headers of an HTTP/1.1 request, bodies are never read
+pub struct HttpRequest {
+    pub method: String,
+    // Path including any query string
+    pub target: String,
+    headers: Vec<(String, String)>,
+}
+
+impl HttpRequest {
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        let (_, query) = self.target.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .filter(|value| !value.is_empty())
+    }
+}
+
+// Reads the request head, None if the client went away or sent more than a GET needs
+pub async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<HttpRequest>> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut lines = request.lines();
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let target = request_line.next().unwrap_or_default().to_string();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    Ok(Some(HttpRequest {
+        method,
+        target,
+        headers,
+    }))
+}
+
+// Writes the status line and headers of a response that ends when the connection closes
+pub async fn write_response_head(
+    stream: &mut TcpStream,
+    status: u16,
+    reason: &str,
+    headers: &[(&str, String)],
+) -> std::io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\nConnection: close\r\n", status, reason);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await
+}
+
+struct Server {
+    source: ReleaseSource,
+    profile: Option<String>,
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    peer_ip: IpAddr,
+    server: Arc<Server>,
+) -> std::io::Result<()> {
+    let Some(request) = read_request(&mut stream).await? else {
+        return Ok(());
+    };
+
+    let client_id = request
+        .header(INSTALLATION_ID_HEADER)
+        .map(str::to_string)
+        .unwrap_or_else(|| peer_ip.to_string());
+
+    let response = respond(&server, &request.method, request.path(), &client_id);
+    println!(
+        "{} {} -> {}",
+        request.method,
+        request.path(),
+        response.status
+    );
+
+    let body = response.body.unwrap_or_default();
+    let headers = if response.status == 204 {
+        Vec::new()
+    } else {
+        vec![
+            ("Content-Type", "application/json".to_string()),
+            ("Content-Length", body.len().to_string()),
+        ]
+    };
+    write_response_head(&mut stream, response.status, response.reason, &headers).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// Serves the Tauri dynamic updater endpoint for the releases of a profile until the process is
+// stopped
+pub async fn serve(
+    address: &str,
+    source: ReleaseSource,
+    profile: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(address).await?;
+    println!(
+        "Serving updates from {} on http://{}",
+        source.describe(),
+        address
+    );
+    println!("Point the updater endpoint at http://<host>/{{{{target}}}}/{{{{arch}}}}/{{{{current_version}}}}");
+
+    let server = Arc::new(Server {
+        source,
+        profile: profile.map(str::to_string),
+    });
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let server = Arc::clone(&server);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, peer.ip(), server).await {
+                eprintln!("Error handling request: {}", e);
+            }
+        });
+    }
+}
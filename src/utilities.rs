@@ -4,6 +4,8 @@ use std::error::Error;
 use std::{fs,fs::File, path::Path};
 use std::io::{self, Write};
 
+use crate::error::JavelinError;
+
 
 pub fn create_default_config_if_not_exists(config_path: &str) -> Result<(), io::Error> {
     // Check if the file already exists
@@ -12,32 +14,106 @@ pub fn create_default_config_if_not_exists(config_path: &str) -> Result<(), io::
         return Ok(());
     }
 
-    // Create a JSON object with the default configuration
-    let default_config = json!({
-        "gist_id": "",
-        "github_pat": "",
-        "github_repo": "",
-        "github_username": "",
-        "secret_key_location": "",
-        "secret_key_password": "",
-    });
-
     // Open the file in write mode and write the JSON content to it
     let mut file = File::create(config_path)?;
-    file.write_all(serde_json::to_string_pretty(&default_config).unwrap().as_bytes())?;
+    file.write_all(serde_json::to_string_pretty(&default_config())?.as_bytes())?;
 
     println!("Config file created at {}", config_path);
 
     Ok(())
 }
 
-pub fn read_value(prompt: &str, value: &mut String) {
-    if value.trim().is_empty() {
+// Values for the settings that have one when no layer sets them. They are filled in after the
+// layers are merged rather than written into generated configs, where they would override the
+// global config.
+pub const SETTING_DEFAULTS: &[(&str, &str)] = &[
+    ("release_host", "github"),
+    ("manifest_host", "gist"),
+    ("local.channel", "stable"),
+    ("manifest_branch.branch", "updates"),
+    ("manifest_branch.path", "latest.json"),
+];
+
+// Every config key, blank, the bottom layer of the merged config and the contents of a generated
+// javelin.conf.json
+pub fn default_config() -> Value {
+    json!({
+        "gist_id": "",
+        "legacy_gist_ids": [],
+        "github_pat": "",
+        "github_repo": "",
+        "github_username": "",
+        "distribution_repo": "",
+        "asset_url": "",
+        "secret_key_location": "",
+        "secret_key_password": "",
+        "required_platforms": [],
+        "rollout_stages": [],
+        "blocked_versions": [],
+        "minimum_version": "",
+        "release_host": "",
+        "gitlab": {
+            "url": "",
+            "project": "",
+            "token": "",
+        },
+        "gitea": {
+            "url": "",
+            "repo": "",
+            "token": "",
+        },
+        "manifest_host": "",
+        "s3": {
+            "endpoint": "",
+            "region": "",
+            "bucket": "",
+            "access_key": "",
+            "secret_key": "",
+            "prefix": "",
+            "public_url": "",
+            "path_style": null,
+            "manifest_cache_control": "",
+        },
+        "local": {
+            "root": "",
+            "channel": "",
+            "base_url": "",
+        },
+        "manifest_branch": {
+            "repo": "",
+            "branch": "",
+            "path": "",
+            "url": "",
+        },
+        "proxy": {
+            "public_url": "",
+            "license_tokens": [],
+        },
+        "build": {
+            "features": [],
+            "args": [],
+            "product_name": "",
+            "identifier": "",
+            "pubkey": "",
+        },
+    })
+}
+
+// Prompts for the value if it's empty, returns whether it was entered at the prompt
+pub fn read_value(prompt: &str, value: &mut String) -> Result<bool, Box<dyn Error>> {
+    let prompted = value.trim().is_empty();
+    if prompted {
         print!("{} empty, Enter {}: ", prompt, prompt);
-        io::stdout().flush().unwrap();
-        io::stdin().read_line(value).expect("Failed to read input");
+        io::stdout().flush()?;
+        io::stdin().read_line(value).map_err(|e| {
+            JavelinError::config(
+                format!("Couldn't read {} from the terminal: {}", prompt, e),
+                "Set it in javelin.conf.json, or run javelin in an interactive terminal",
+            )
+        })?;
     }
     *value = value.trim().to_string(); // Remove trailing newline
+    Ok(prompted)
 }
 
 pub fn update_tauri_config_endpoint(
@@ -50,7 +126,7 @@ pub fn update_tauri_config_endpoint(
 
     // Navigate to the updater.endpoints array and update it
     if let Some(updater) = config["tauri"]["updater"].as_object_mut() {
-        updater["endpoints"] = serde_json::json!([new_endpoint]);
+        updater.insert("endpoints".to_string(), serde_json::json!([new_endpoint]));
     } else {
         return Err("Failed to find updater configuration in Tauri config".into());
     }
@@ -60,15 +136,68 @@ pub fn update_tauri_config_endpoint(
 
     Ok(())
 }
+
+// Sets values in a JSON file such as tauri.conf.json or javelin.conf.json, creating missing parent
+// objects, and returns the previous contents so they can be written back
+pub fn patch_json_file(
+    config_path: &str,
+    patches: &[(&[&str], Value)],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let original = fs::read_to_string(config_path)?;
+    let mut config: Value = serde_json::from_str(&original)?;
+
+    for (key_path, value) in patches {
+        let mut current = &mut config;
+        for &key in key_path.iter() {
+            current = current
+                .as_object_mut()
+                .ok_or_else(|| format!("Expected a JSON object in {}", config_path))?
+                .entry(key)
+                .or_insert_with(|| json!({}));
+        }
+        *current = value.clone();
+    }
+
+    fs::write(config_path, serde_json::to_string_pretty(&config)?)?;
+
+    Ok(original)
+}
 #[macro_export]
 macro_rules! exit_with_error {
-    ($config_path:expr, $current_version:expr) => {{
+    // $exit_code comes from error::report, which has printed the error and its hint
+    ($config_path:expr, $current_version:expr, $exit_code:expr) => {{
+        println!("Error occurred in file: {}, line: {}", file!(), line!());
+        let _result = reset_version_in_config($config_path, $current_version);
+        release_run_lock();
+        std::process::exit($exit_code);
+    }};
+    // Once a release journal exists, also undo the remote objects it created
+    ($config_path:expr, $current_version:expr, $journal:expr, $rollback:expr, $exit_code:expr) => {{
         println!("Error occurred in file: {}, line: {}", file!(), line!());
+        let (host, token, javelin_config_path, keep_on_failure) = $rollback;
+        rollback_remote_objects($journal, host, token, javelin_config_path, keep_on_failure).await;
         let _result = reset_version_in_config($config_path, $current_version);
-        std::process::exit(1);
+        release_run_lock();
+        std::process::exit($exit_code);
     }};
 }
 
+// The value of a step that can't fail quietly once the version is bumped, otherwise the error is
+// reported and the run exits through exit_with_error!, rolling back what the release created
+#[macro_export]
+macro_rules! or_exit_with_error {
+    ($result:expr, $context:expr, $config_path:expr, $current_version:expr, $journal:expr, $rollback:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(e) => {
+                let error: Box<dyn std::error::Error> = e.into();
+                let code = report($context, error.as_ref());
+                exit_with_error!($config_path, $current_version, $journal, $rollback, code);
+            }
+        }
+    };
+}
+
 pub fn read_and_update_version<P: AsRef<Path>>(
     path: P,
     update_type: UpdateType,
@@ -168,7 +297,12 @@ pub fn update_entry_in_config(
     // Navigate to the specified key
     let mut current = &mut config;
     for &key in key_path.iter().take(key_path.len() - 1) {
-        current = current.get_mut(key).ok_or("Key path not found")?;
+        // Missing parent objects are created, e.g. a "gitlab" section in an older config
+        current = current
+            .as_object_mut()
+            .ok_or("Key path not found")?
+            .entry(key)
+            .or_insert_with(|| json!({}));
     }
 
     // Assuming the last element in `key_path` is the actual key to update
@@ -185,12 +319,6 @@ pub fn update_entry_in_config(
     Ok(())
 }
 
-pub fn read_config<P: AsRef<Path>>(path: P) -> Result<Config, Box<dyn std::error::Error>> {
-    let config_str = fs::read_to_string(path)?;
-    let config: Config = serde_json::from_str(&config_str)?;
-    Ok(config)
-}
-
 pub fn read_tauri_config<P: AsRef<Path>>(
     path: P,
 ) -> Result<TauriConfig, Box<dyn std::error::Error>> {
@@ -226,10 +354,180 @@ pub struct Updater {
 pub struct Config {
     pub github_username: String,
     pub github_repo: String,
+    // "owner/repo" releases and assets are published to instead of github_repo, e.g. a public
+    // repo for a private app
+    #[serde(default)]
+    pub distribution_repo: String,
+    // Url written to the manifest: "api", "browser" or a template like https://cdn.example.com/{tag}/{name}.
+    // Empty uses the browser url for a distribution_repo and the API url otherwise
+    #[serde(default)]
+    pub asset_url: String,
     pub github_pat: String,
     pub secret_key_location: String,
     pub secret_key_password: String,
     pub gist_id: String,
+    // Gists still holding per platform manifests from before 'javelin migrate-manifest'. Each
+    // release also updates its platform's file there, for apps installed before the migration
+    #[serde(default)]
+    pub legacy_gist_ids: Vec<String>,
+    // Platforms that must publish a version before the manifest offers it, empty means all listed
+    #[serde(default)]
+    pub required_platforms: Vec<String>,
+    // Percentages new versions are rolled out in through 'javelin serve', empty offers them to everyone
+    #[serde(default)]
+    pub rollout_stages: Vec<u8>,
+    // Versions that must never be offered and whose clients are always told to update
+    #[serde(default)]
+    pub blocked_versions: Vec<String>,
+    // Clients older than this are always told to update, empty for no minimum
+    #[serde(default)]
+    pub minimum_version: String,
+    // Where releases and assets are published: "github" (default), "gitlab", "gitea", "forgejo", "s3" or "local"
+    #[serde(default)]
+    pub release_host: String,
+    // Where the updater manifest is published: "gist" (default), "branch", "release", "s3" or "local"
+    #[serde(default)]
+    pub manifest_host: String,
+    #[serde(default)]
+    pub gitlab: GitlabConfig,
+    #[serde(default)]
+    pub gitea: GiteaConfig,
+    #[serde(default)]
+    pub s3: S3Config,
+    #[serde(default)]
+    pub local: LocalConfig,
+    #[serde(default)]
+    pub manifest_branch: ManifestBranchConfig,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub build: BuildConfig,
+}
+
+impl Config {
+    // Only the gist manifest store needs a gist created up front
+    pub fn uses_gist(&self) -> bool {
+        self.manifest_host.is_empty() || self.manifest_host == "gist"
+    }
+
+    // Deployments publishing only to S3 or a local directory need no Github details at all
+    pub fn uses_github(&self) -> bool {
+        self.uses_gist()
+            || self.manifest_host == "branch"
+            || self.manifest_host == "release"
+            || self.release_host.is_empty()
+            || self.release_host == "github"
+    }
+}
+
+// How the app is built, usually set per profile
+#[derive(Deserialize, Debug, Default)]
+pub struct BuildConfig {
+    // Cargo features passed to 'tauri build --features'
+    #[serde(default)]
+    pub features: Vec<String>,
+    // Extra arguments for 'tauri build'
+    #[serde(default)]
+    pub args: Vec<String>,
+    // Written into tauri.conf.json for the duration of the build, empty keeps the file's value
+    #[serde(default)]
+    pub product_name: String,
+    #[serde(default)]
+    pub identifier: String,
+    // Updater public key matching the profile's signing key
+    #[serde(default)]
+    pub pubkey: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct GitlabConfig {
+    // Instance url, defaults to https://gitlab.com
+    #[serde(default)]
+    pub url: String,
+    // Numeric project id or "group/project" path
+    #[serde(default)]
+    pub project: String,
+    #[serde(default)]
+    pub token: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct GiteaConfig {
+    // Instance url, e.g. https://git.example.com
+    #[serde(default)]
+    pub url: String,
+    // "owner/repo"
+    #[serde(default)]
+    pub repo: String,
+    #[serde(default)]
+    pub token: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct S3Config {
+    // Leave blank for AWS, otherwise e.g. http://localhost:9000 for MinIO
+    #[serde(default)]
+    pub endpoint: String,
+    // Defaults to us-east-1, use "auto" for R2
+    #[serde(default)]
+    pub region: String,
+    #[serde(default)]
+    pub bucket: String,
+    #[serde(default)]
+    pub access_key: String,
+    #[serde(default)]
+    pub secret_key: String,
+    // Key prefix for everything javelin uploads
+    #[serde(default)]
+    pub prefix: String,
+    // Public base url objects are served from, defaults to the bucket url
+    #[serde(default)]
+    pub public_url: String,
+    // Defaults to path style for custom endpoints and virtual host style for AWS
+    #[serde(default)]
+    pub path_style: Option<bool>,
+    // Cache-Control for the manifest object, defaults to "no-cache, max-age=0"
+    #[serde(default)]
+    pub manifest_cache_control: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct LocalConfig {
+    // Directory served by the web server
+    #[serde(default)]
+    pub root: String,
+    // Defaults to "stable"
+    #[serde(default)]
+    pub channel: String,
+    // Url the root directory is served from
+    #[serde(default)]
+    pub base_url: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ProxyConfig {
+    // Url clients reach 'javelin proxy' at, defaults to http://<Host header>
+    #[serde(default)]
+    pub public_url: String,
+    // Per customer tokens clients must send, empty to serve everyone
+    #[serde(default)]
+    pub license_tokens: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ManifestBranchConfig {
+    // "owner/repo", defaults to github_username/github_repo
+    #[serde(default)]
+    pub repo: String,
+    // Defaults to "updates"
+    #[serde(default)]
+    pub branch: String,
+    // Path of the manifest in the branch, defaults to "latest.json"
+    #[serde(default)]
+    pub path: String,
+    // Url the updater fetches the manifest from, e.g. a Pages url. Defaults to the raw file url
+    #[serde(default)]
+    pub url: String,
 }
 
 #[derive(Debug)]